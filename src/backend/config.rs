@@ -0,0 +1,171 @@
+//! Configuration of the back-end `Service`.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel::Sender;
+use wg_2024::{network::NodeId, packet::NodeType};
+
+use super::{
+    ApiResponse, Clock, CodecKind, DatabaseSnapshot, KnownTopology, MessageEncoding,
+    NodeSigningKey, NodeVerifyingKey, PeerKey, RetentionPolicy, SystemClock,
+};
+
+/// Constraints on the neighbors a client may be connected to.
+///
+/// The policy is enforced when the `Service` is created as well as when the
+/// simulation controller adds or removes senders at runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NeighborPolicy {
+    pub min_neighbors: usize,
+    pub max_neighbors: usize,
+    pub allowed_node_types: Vec<NodeType>,
+}
+
+impl Default for NeighborPolicy {
+    /// A client must be connected to one or two drones.
+    fn default() -> Self {
+        NeighborPolicy {
+            min_neighbors: 1,
+            max_neighbors: 2,
+            allowed_node_types: vec![NodeType::Drone],
+        }
+    }
+}
+
+impl NeighborPolicy {
+    /// Checks that `amount` of neighbors is within the configured bounds.
+    ///
+    /// # Errors
+    /// Returns a `String`-error describing the violated bound.
+    pub fn validate_amount(&self, amount: usize) -> Result<(), String> {
+        if amount < self.min_neighbors || amount > self.max_neighbors {
+            return Err(format!(
+                "There are {amount} neighbors connected but there must be {}-{}.",
+                self.min_neighbors, self.max_neighbors,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that a neighbor of the given type is allowed.
+    ///
+    /// # Errors
+    /// Returns a `String`-error if the node type is not allowed.
+    pub fn validate_node_type(&self, node_id: NodeId, node_type: NodeType) -> Result<(), String> {
+        if !self.allowed_node_types.contains(&node_type) {
+            return Err(format!(
+                "Node {node_id} is of type {node_type:?} which is not allowed as a neighbor."
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Handling of received messages whose sender cannot be authenticated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// Signatures are not checked.
+    #[default]
+    Ignore,
+    /// Unverified messages are saved flagged as unverified and reported.
+    Flag,
+    /// Unverified messages are discarded and reported.
+    Require,
+}
+
+/// Optional settings for the back-end `Service`.
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    /// Constraints on connected neighbors.
+    pub neighbor_policy: NeighborPolicy,
+    /// Node types of neighbors known in advance. Neighbors whose type is
+    /// unknown are only checked against the amount constraints.
+    pub neighbor_types: HashMap<NodeId, NodeType>,
+    /// Rules for discarding stored messages and fragments.
+    pub retention: RetentionPolicy,
+    /// Codec splitting messages into fragments. Every node exchanging
+    /// messages with this client must use the same codec.
+    pub codec: CodecKind,
+    /// Serialization of outgoing messages. Incoming messages are decoded
+    /// whatever their encoding.
+    pub message_encoding: MessageEncoding,
+    /// Serialization of outgoing messages for specific destinations,
+    /// overriding `message_encoding`.
+    pub destination_encodings: HashMap<NodeId, MessageEncoding>,
+    /// Outgoing messages whose serialized size reaches this many bytes are
+    /// compressed before fragmentation. `None` disables compression.
+    pub compression_threshold: Option<usize>,
+    /// Whether outgoing messages carry an end-to-end checksum. Messages
+    /// failing their checksum are discarded and the sender is NACKed to
+    /// re-send them.
+    pub integrity_checks: bool,
+    /// Keys shared with peers. Messages to a peer with a key are encrypted
+    /// end to end and encrypted messages from it are decrypted.
+    pub peer_keys: HashMap<NodeId, PeerKey>,
+    /// Key outgoing messages are signed with. `None` sends unsigned messages.
+    pub signing_key: Option<NodeSigningKey>,
+    /// Keys signed messages from other nodes are verified with.
+    pub verifying_keys: HashMap<NodeId, NodeVerifyingKey>,
+    /// Handling of messages that are unsigned, signed by an unknown node or
+    /// carry a forged signature.
+    pub signature_policy: SignaturePolicy,
+    /// Interval of periodic maintenance such as evicting stale sessions.
+    pub housekeeping_interval: Duration,
+    /// Time after which an incomplete inbound message is abandoned.
+    /// `None`, the default, keeps incomplete messages forever.
+    pub reassembly_timeout: Option<Duration>,
+    /// Whether every fragment of an abandoned message is NACKed so that the
+    /// sender re-sends the whole message.
+    pub nack_abandoned_sessions: bool,
+    /// State the database is initialized with, e.g. a dump of an earlier run.
+    pub initial_state: Option<DatabaseSnapshot>,
+    /// Network the client knows from the start, so that messages can be
+    /// sent before a flood completes. The SC is notified of it on start-up.
+    pub initial_topology: Option<KnownTopology>,
+    /// File the database state is written to as JSON when the client crashes.
+    pub state_dump_path: Option<PathBuf>,
+    /// File every packet the client sends and receives is logged to as
+    /// JSON lines. `None` records nothing.
+    pub traffic_log_path: Option<PathBuf>,
+    /// Seed of the random choices of the client, such as route selection.
+    /// A run can be replayed by passing the seed it logged on start-up.
+    /// `None` picks a random seed.
+    pub rng_seed: Option<u64>,
+    /// Clock timestamps, timeouts and periodic maintenance are based on.
+    /// Tests can pass a `ManualClock` to control time instead of sleeping.
+    pub clock: Arc<dyn Clock>,
+    /// Channel used to answer `Command`s that do not have a dedicated channel.
+    pub api_response_channel: Option<Sender<ApiResponse>>,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        ServiceConfig {
+            neighbor_policy: NeighborPolicy::default(),
+            neighbor_types: HashMap::new(),
+            retention: RetentionPolicy::default(),
+            codec: CodecKind::default(),
+            message_encoding: MessageEncoding::default(),
+            destination_encodings: HashMap::new(),
+            compression_threshold: None,
+            integrity_checks: false,
+            peer_keys: HashMap::new(),
+            signing_key: None,
+            verifying_keys: HashMap::new(),
+            signature_policy: SignaturePolicy::default(),
+            housekeeping_interval: Duration::from_secs(1),
+            reassembly_timeout: None,
+            nack_abandoned_sessions: false,
+            initial_state: None,
+            initial_topology: None,
+            state_dump_path: None,
+            traffic_log_path: None,
+            rng_seed: None,
+            clock: Arc::new(SystemClock),
+            api_response_channel: None,
+        }
+    }
+}
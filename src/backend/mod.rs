@@ -1,5 +1,6 @@
 //! Module provides public back-end functionality for Advanced Programming 2024 client.
 #![allow(clippy::too_many_arguments)]
+pub mod config;
 
 use std::collections::HashMap;
 
@@ -15,6 +16,20 @@ use wg_2024::{
 
 use crate::network::router::Router;
 
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+pub use crate::database::message::{MessageID, MessageMeta, SenderID, SessionID};
+pub use crate::database::retention::{MemoryStats, RetentionPolicy};
+pub use crate::database::session::{SessionDirection, SessionStatus};
+pub use crate::database::snapshot::DatabaseSnapshot;
+pub use crate::network::capture::{TrafficDirection, TrafficRecord, replay_inbound};
+pub use crate::network::graph::{KnownTopology, TopologyNode};
+pub use crate::network::stats::{NackCounts, PacketCounts, Stats};
+pub use crate::packet::codec::CodecKind;
+pub use crate::packet::encryption::PeerKey;
+pub use crate::packet::envelope::MessageEncoding;
+pub use crate::packet::signature::{Authenticity, NodeSigningKey, NodeVerifyingKey};
+pub use config::{NeighborPolicy, ServiceConfig, SignaturePolicy};
+
 pub struct Service {
     router: Router,
 }
@@ -23,9 +38,98 @@ pub struct Service {
 pub enum Command {
     GetEdgeNodesFromFlood,
     InitializeFlood,
-    GetUnreadMessagesFromServer,
+    /// Hands over the unread messages received from the given server,
+    /// marking them as read.
+    GetUnreadMessagesFromServer(NodeId),
     GetClientsFromServer(u8),
     SendMessage(Message),
+    /// Lists messages exchanged with a peer, or with all peers if `peer` is
+    /// `None`, ordered by arrival. Answered with `ApiResponse::Inbox`.
+    ListMessages {
+        peer: Option<NodeId>,
+        offset: usize,
+        limit: usize,
+    },
+    MarkAsRead(MessageID),
+    MarkAsUnread(MessageID),
+    /// Answered with `ApiResponse::UnreadCounts`.
+    GetUnreadCounts,
+    /// Returns every message exchanged with a peer as `ApiResponse::Transcript`.
+    GetConversation(NodeId),
+    /// Answered with `ApiResponse::MemoryStats`.
+    GetMemoryStats,
+    /// Runtime statistics of the client, answered with `ApiResponse::Stats`.
+    GetStats,
+    /// The network as known to the client, answered with `ApiResponse::Topology`.
+    GetTopology,
+    /// Adds a topology, e.g. one parsed with `KnownTopology::from_network_config`,
+    /// to the network known to the client.
+    LoadTopology(KnownTopology),
+    /// Answered with `ApiResponse::State`.
+    ExportState,
+    /// Transfer status of a session, answered with `ApiResponse::SessionStatus`.
+    /// Outbound sessions have this client as the sender.
+    GetSessionStatus {
+        session_id: u64,
+        sender_id: NodeId,
+    },
+    /// Answered with `ApiResponse::SessionStatuses`.
+    ListSessionStatuses,
+    /// Encrypts messages exchanged with `peer` using `key` from now on.
+    SetPeerKey {
+        peer: NodeId,
+        key: PeerKey,
+    },
+    /// Stops encrypting messages to the peer.
+    RemovePeerKey(NodeId),
+    /// Verifies signed messages from `node` with `key` from now on.
+    RegisterVerifyingKey {
+        node: NodeId,
+        key: NodeVerifyingKey,
+    },
+    RemoveVerifyingKey(NodeId),
+}
+
+/// Simulation controller command without the channel payload of `DroneCommand`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControllerCommand {
+    AddSender(NodeId),
+    RemoveSender(NodeId),
+    SetPacketDropRate(f32),
+    Crash,
+}
+
+/// Events reported back to the simulation controller in addition to `NodeEvent`s.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServiceEvent {
+    CommandAccepted(ControllerCommand),
+    CommandRejected(ControllerCommand, String),
+    /// The command took effect but could not be completed, e.g. the client
+    /// crashed as commanded but failed to dump its state.
+    CommandFailed(ControllerCommand, String),
+    /// An incoming message did not receive all of its fragments in time.
+    MessageAbandoned {
+        session_id: u64,
+        sender_id: NodeId,
+        received_fragments: u64,
+        total_fragments: u64,
+    },
+    /// An incoming message failed its integrity check and was discarded.
+    /// The sender was asked to retransmit it.
+    MessageCorrupted {
+        session_id: u64,
+        sender_id: NodeId,
+        reason: String,
+    },
+    /// The sender of an incoming message could not be authenticated. The
+    /// message was discarded if `rejected`, otherwise it was saved flagged
+    /// as unverified.
+    UnverifiedMessage {
+        session_id: u64,
+        sender_id: NodeId,
+        authenticity: Authenticity,
+        rejected: bool,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -37,19 +141,93 @@ pub struct UnreadMessagesFromServer(pub Vec<Message>);
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClientsFromServer(pub Vec<u8>);
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboxEntry {
+    pub id: MessageID,
+    pub message: Message,
+    pub read: bool,
+}
+
+/// A page of messages starting at `offset` out of `total` matching messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboxPage {
+    pub entries: Vec<InboxEntry>,
+    pub offset: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    pub id: MessageID,
+    pub message: Message,
+    pub meta: MessageMeta,
+    pub read: bool,
+}
+
+/// All messages exchanged with `peer` ordered by arrival.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    pub peer: NodeId,
+    pub entries: Vec<TranscriptEntry>,
+}
+
+/// Amount of unread messages per peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnreadCounts(pub Vec<(NodeId, usize)>);
+
+/// Responses to `Command`s that do not have a dedicated channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiResponse {
+    Inbox(InboxPage),
+    UnreadCounts(UnreadCounts),
+    Transcript(Transcript),
+    MemoryStats(MemoryStats),
+    Stats(Stats),
+    Topology(KnownTopology),
+    State(DatabaseSnapshot),
+    SessionStatus(Option<SessionStatus>),
+    SessionStatuses(Vec<SessionStatus>),
+}
+
 impl Service {
     /// Function will start the main loop of the back-end.
     /// Main loop consist of listening to incoming and outgoing packets
     /// as well as simulation controller related interactions.
-    /// # Errors
-    /// Propagates `Error`s encountered with crossbeam-channels.
-    pub fn run(&mut self) -> !{
+    ///
+    /// The function returns once the simulation controller sends
+    /// `DroneCommand::Crash` and the client has shut down.
+    pub fn run(&mut self) {
         self.router.listen_channels();
     }
 
+    /// Shares `key` with `peer`. Messages sent to the peer are encrypted and
+    /// encrypted messages received from it can be decrypted.
+    pub fn set_peer_key(&mut self, peer: NodeId, key: PeerKey) {
+        self.router.set_peer_key(peer, key);
+    }
+
+    /// Forgets the key shared with `peer`, returning it if there was one.
+    pub fn remove_peer_key(&mut self, peer: NodeId) -> Option<PeerKey> {
+        self.router.remove_peer_key(peer)
+    }
+
+    /// Registers the key signed messages from `node` are verified with.
+    pub fn register_verifying_key(&mut self, node: NodeId, key: NodeVerifyingKey) {
+        self.router.register_verifying_key(node, key);
+    }
+
+    /// Forgets the verifying key of `node`, returning it if there was one.
+    pub fn remove_verifying_key(&mut self, node: NodeId) -> Option<NodeVerifyingKey> {
+        self.router.remove_verifying_key(node)
+    }
+
     /// Creates a new back-end instance which can be then used to
     /// provide services for the front-end.
     ///
+    /// Every `DroneCommand` received on `sc_command_channel` is answered with
+    /// a `ServiceEvent` on `sc_report_channel`, which also carries events
+    /// `NodeEvent` cannot express, such as abandoned or corrupted messages.
+    ///
     /// # Returns
     /// Function returns new (back-end) `Service`-struct if the passed argument
     /// are valid.
@@ -63,13 +241,46 @@ impl Service {
         node_id: u8,
         sc_event_channel: Sender<NodeEvent>,
         sc_command_channel: Receiver<DroneCommand>,
+        sc_report_channel: Sender<ServiceEvent>,
         neighbor_packet_channels: HashMap<NodeId, Sender<Packet>>,
         incoming_packet_channel: Receiver<Packet>,
         api_command_recv_channel: Receiver<Command>,
         outbound_response_for_flood: Sender<ListOfDiscoveredEdgeNodes>,
         outbound_undread_messages: Sender<UnreadMessagesFromServer>,
     ) -> Result<Self, String> {
-        Self::validate_options(&neighbor_packet_channels, node_id)?;
+        Self::with_config(
+            node_id,
+            sc_event_channel,
+            sc_command_channel,
+            sc_report_channel,
+            neighbor_packet_channels,
+            incoming_packet_channel,
+            api_command_recv_channel,
+            outbound_response_for_flood,
+            outbound_undread_messages,
+            ServiceConfig::default(),
+        )
+    }
+
+    /// Creates a new back-end instance with the given `ServiceConfig`.
+    ///
+    /// # Errors
+    /// If arguments are invalid, `String`-error is returned. The neighbors
+    /// are validated against `config.neighbor_policy` and
+    /// `config.initial_state` must belong to a client with the same ID.
+    pub fn with_config(
+        node_id: u8,
+        sc_event_channel: Sender<NodeEvent>,
+        sc_command_channel: Receiver<DroneCommand>,
+        sc_report_channel: Sender<ServiceEvent>,
+        neighbor_packet_channels: HashMap<NodeId, Sender<Packet>>,
+        incoming_packet_channel: Receiver<Packet>,
+        api_command_recv_channel: Receiver<Command>,
+        outbound_response_for_flood: Sender<ListOfDiscoveredEdgeNodes>,
+        outbound_undread_messages: Sender<UnreadMessagesFromServer>,
+        config: ServiceConfig,
+    ) -> Result<Self, String> {
+        Self::validate_options(&neighbor_packet_channels, node_id, &config)?;
 
         let router = Router::new(
             node_id,
@@ -77,10 +288,13 @@ impl Service {
             sc_command_channel,
             neighbor_packet_channels,
             sc_event_channel,
+            sc_report_channel,
             api_command_recv_channel,
             outbound_response_for_flood,
             outbound_undread_messages,
-        );
+            config,
+        )
+        .map_err(|e| e.to_string())?;
         let service = Service { router };
         Ok(service)
     }
@@ -88,6 +302,7 @@ impl Service {
     fn validate_options(
         neighbors: &HashMap<NodeId, Sender<Packet>>,
         node_id: u8,
+        config: &ServiceConfig,
     ) -> Result<(), String> {
         // Ensure that the client ID is not as a recipient
         if neighbors.contains_key(&node_id) {
@@ -95,11 +310,15 @@ impl Service {
         }
 
         // Ensure that the amount of connected drones is valid.
-        if neighbors.len() > 2 || neighbors.is_empty() {
-            return Err(format!(
-                "There are {} drones connected when the there must be 1-2 connected drones.",
-                neighbors.len(),
-            ));
+        config.neighbor_policy.validate_amount(neighbors.len())?;
+
+        // Ensure that neighbors with known types are allowed.
+        for neighbor in neighbors.keys() {
+            if let Some(node_type) = config.neighbor_types.get(neighbor) {
+                config
+                    .neighbor_policy
+                    .validate_node_type(*neighbor, *node_type)?;
+            }
         }
 
         Ok(())
@@ -125,7 +344,7 @@ mod tests {
         let mut neighbors = HashMap::new();
         neighbors.insert(node_id, dummy_sender());
 
-        let result = Service::validate_options(&neighbors, node_id);
+        let result = Service::validate_options(&neighbors, node_id, &ServiceConfig::default());
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "Own ID is used as a recipient.");
     }
@@ -139,11 +358,11 @@ mod tests {
         neighbors.insert(3, dummy_sender());
         neighbors.insert(4, dummy_sender()); // > 2 neighbors
 
-        let result = Service::validate_options(&neighbors, node_id);
+        let result = Service::validate_options(&neighbors, node_id, &ServiceConfig::default());
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err(),
-            "There are 3 drones connected when the there must be 1-2 connected drones."
+            "There are 3 neighbors connected but there must be 1-2."
         );
     }
 
@@ -153,11 +372,11 @@ mod tests {
         let node_id: NodeId = 1;
         let neighbors = HashMap::new(); // empty
 
-        let result = Service::validate_options(&neighbors, node_id);
+        let result = Service::validate_options(&neighbors, node_id, &ServiceConfig::default());
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err(),
-            "There are 0 drones connected when the there must be 1-2 connected drones."
+            "There are 0 neighbors connected but there must be 1-2."
         );
     }
 
@@ -167,12 +386,52 @@ mod tests {
         let mut neighbors = HashMap::new();
         neighbors.insert(2, dummy_sender());
 
-        let result = Service::validate_options(&neighbors, node_id);
+        let result = Service::validate_options(&neighbors, node_id, &ServiceConfig::default());
         assert!(result.is_ok());
 
         neighbors.insert(3, dummy_sender()); // now 2 neighbors
 
-        let result = Service::validate_options(&neighbors, node_id);
+        let result = Service::validate_options(&neighbors, node_id, &ServiceConfig::default());
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_validate_options_custom_neighbor_amount() {
+        let node_id: NodeId = 1;
+        let mut neighbors = HashMap::new();
+        for id in 2..=5 {
+            neighbors.insert(id, dummy_sender());
+        }
+        let config = ServiceConfig {
+            neighbor_policy: NeighborPolicy {
+                min_neighbors: 1,
+                max_neighbors: 4,
+                ..NeighborPolicy::default()
+            },
+            ..ServiceConfig::default()
+        };
+
+        let result = Service::validate_options(&neighbors, node_id, &config);
         assert!(result.is_ok());
+
+        neighbors.insert(6, dummy_sender());
+        let result = Service::validate_options(&neighbors, node_id, &config);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn test_validate_options_disallowed_node_type() {
+        let node_id: NodeId = 1;
+        let mut neighbors = HashMap::new();
+        neighbors.insert(2, dummy_sender());
+        let mut config = ServiceConfig::default();
+        config.neighbor_types.insert(2, NodeType::Server);
+
+        let result = Service::validate_options(&neighbors, node_id, &config);
+        assert_eq!(
+            result.unwrap_err(),
+            "Node 2 is of type Server which is not allowed as a neighbor."
+        );
     }
 }
@@ -0,0 +1,149 @@
+//! Source of time for timeouts, timestamps and periodic work.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crossbeam_channel::{Receiver, Sender, bounded};
+
+/// Provides the current time and timers running on that time.
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Returns the current time in milliseconds since UNIX epoch.
+    fn now_millis(&self) -> u64;
+
+    /// Returns a channel receiving a message every `interval` of this
+    /// clock's time. Like `crossbeam_channel::tick`, ticks that are not
+    /// received in time are dropped.
+    fn ticker(&self, interval: Duration) -> Receiver<Instant>;
+}
+
+/// The wall clock of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| {
+                u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+            })
+    }
+
+    fn ticker(&self, interval: Duration) -> Receiver<Instant> {
+        crossbeam_channel::tick(interval)
+    }
+}
+
+/// A clock that only moves when advanced, for tests of time-based behavior.
+///
+/// Clones share the same time, so a test can keep a clone and advance the
+/// clock used by a running `Service`.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    state: Arc<Mutex<ManualClockState>>,
+}
+
+#[derive(Debug, Default)]
+struct ManualClockState {
+    now: u64,
+    tickers: Vec<ManualTicker>,
+}
+
+#[derive(Debug)]
+struct ManualTicker {
+    interval: u64,
+    next_tick: u64,
+    channel: Sender<Instant>,
+}
+
+impl ManualClock {
+    /// Creates a clock showing `start_millis` milliseconds since UNIX epoch.
+    pub fn new(start_millis: u64) -> Self {
+        let clock = ManualClock::default();
+        clock.lock().now = start_millis;
+        clock
+    }
+
+    /// Moves the clock forward, firing the tickers that become due.
+    pub fn advance(&self, duration: Duration) {
+        let duration = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        let mut state = self.lock();
+        state.now = state.now.saturating_add(duration);
+        let now = state.now;
+        state.tickers.retain_mut(|ticker| {
+            if now < ticker.next_tick {
+                return true;
+            }
+            let missed_ticks = (now - ticker.next_tick) / ticker.interval + 1;
+            ticker.next_tick = ticker
+                .next_tick
+                .saturating_add(missed_ticks.saturating_mul(ticker.interval));
+            // A full channel means the previous tick has not been received yet.
+            !matches!(
+                ticker.channel.try_send(Instant::now()),
+                Err(crossbeam_channel::TrySendError::Disconnected(_))
+            )
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ManualClockState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.lock().now
+    }
+
+    fn ticker(&self, interval: Duration) -> Receiver<Instant> {
+        let interval = u64::try_from(interval.as_millis())
+            .unwrap_or(u64::MAX)
+            .max(1);
+        let (channel, receiver) = bounded(1);
+        let mut state = self.lock();
+        let next_tick = state.now.saturating_add(interval);
+        state.tickers.push(ManualTicker {
+            interval,
+            next_tick,
+            channel,
+        });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_only_moves_when_advanced() {
+        let clock = ManualClock::new(1_000);
+        assert_eq!(clock.now_millis(), 1_000);
+
+        let shared = clock.clone();
+        shared.advance(Duration::from_millis(250));
+        assert_eq!(clock.now_millis(), 1_250);
+    }
+
+    #[test]
+    fn test_manual_clock_ticker() {
+        let clock = ManualClock::new(0);
+        let ticker = clock.ticker(Duration::from_secs(1));
+
+        clock.advance(Duration::from_millis(999));
+        assert!(ticker.try_recv().is_err());
+
+        clock.advance(Duration::from_millis(1));
+        assert!(ticker.try_recv().is_ok());
+
+        // Missed ticks are dropped.
+        clock.advance(Duration::from_secs(5));
+        assert!(ticker.try_recv().is_ok());
+        assert!(ticker.try_recv().is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(ticker.try_recv().is_ok());
+    }
+}
@@ -1,5 +1,6 @@
 use core::fmt;
 
+use messages::Message;
 use serde::{Deserialize, Serialize};
 
 #[derive(Hash, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -9,6 +10,25 @@ pub struct SessionID(pub u64);
 #[derive(Hash, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SenderID(pub u8);
 
+/// Bookkeeping stored alongside every message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageMeta {
+    /// The node on the other end of the conversation.
+    pub peer: u8,
+    /// Time the message was sent by this client, in milliseconds since UNIX epoch.
+    pub sent_at: Option<u64>,
+    /// Time the message was received by this client, in milliseconds since UNIX epoch.
+    pub received_at: Option<u64>,
+    /// The request this message is a response to.
+    pub in_reply_to: Option<MessageID>,
+    /// The response given to this message if it is a request.
+    pub replied_by: Option<MessageID>,
+    /// Whether the message was saved although its sender could not be
+    /// authenticated.
+    #[serde(default)]
+    pub unverified: bool,
+}
+
 impl fmt::Display for SessionID {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -25,3 +45,14 @@ impl fmt::Display for MessageID {
         write!(f, "{}:{}", self.0, self.1)
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseMessage {
+    pub message_id: String,
+    pub session_id: String,
+    pub sender_id: u8,
+    pub message: Message,
+    pub meta: MessageMeta,
+    pub read: bool,
+    pub sent_to_sc: bool,
+}
@@ -2,36 +2,82 @@
 #![allow(dead_code)]
 pub mod message;
 pub mod packet;
+pub mod retention;
+pub mod session;
+pub mod snapshot;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Result, anyhow};
-use message::{MessageID, SenderID, SessionID};
-use messages::Message;
+use message::{MessageID, MessageMeta, SenderID, SessionID};
+use messages::{Message, MessageType};
 use packet::{FragmentID, PacketID, PacketID2};
+use retention::RetentionPolicy;
+use session::SessionTrace;
 
+use crate::clock::{Clock, SystemClock};
+
+pub(crate) use wg_2024::network::SourceRoutingHeader;
 pub(crate) use wg_2024::packet::{Packet, PacketType};
 
 pub struct Database {
+    node_id: u8,
     messages: HashMap<MessageID, Message>,
+    message_meta: HashMap<MessageID, MessageMeta>,
+    message_order: Vec<MessageID>,
+    conversations: HashMap<u8, Vec<MessageID>>,
+    pending_requests: HashMap<u8, VecDeque<MessageID>>,
     packets: HashMap<PacketID2, PacketStore>,
     packets_sent_to_sc: HashSet<PacketID>,
     messages_sent_to_sc: HashSet<MessageID>,
     messages_read: HashSet<MessageID>,
     packets_received_ack: HashSet<PacketID>,
+    session_traces: HashMap<PacketID2, SessionTrace>,
+    /// Inbound sessions whose message was removed by retention.
+    evicted_sessions: HashSet<PacketID2>,
+    evicted_session_order: VecDeque<PacketID2>,
+    retention: RetentionPolicy,
+    clock: Arc<dyn Clock>,
 }
 
 impl Database {
-    pub fn new() -> Self {
+    pub fn new(node_id: u8) -> Self {
+        Self::with_retention_policy(node_id, RetentionPolicy::default())
+    }
+
+    pub fn with_retention_policy(node_id: u8, retention: RetentionPolicy) -> Self {
         Database {
+            node_id,
             messages: HashMap::new(),
+            message_meta: HashMap::new(),
+            message_order: Vec::new(),
+            conversations: HashMap::new(),
+            pending_requests: HashMap::new(),
             packets: HashMap::new(),
             packets_sent_to_sc: HashSet::new(),
             messages_sent_to_sc: HashSet::new(),
             messages_read: HashSet::new(),
             packets_received_ack: HashSet::new(),
+            session_traces: HashMap::new(),
+            evicted_sessions: HashSet::new(),
+            evicted_session_order: VecDeque::new(),
+            retention,
+            clock: Arc::new(SystemClock),
         }
     }
+
+    /// Replaces the clock timestamps and timeouts are measured with.
+    ///
+    /// Incomplete sessions restart their reassembly timeout on the new clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        let now = clock.now_millis();
+        for packet_store in self.packets.values_mut() {
+            packet_store.last_activity = now;
+        }
+        self.clock = clock;
+    }
 }
 
 struct PacketStore {
@@ -39,23 +85,100 @@ struct PacketStore {
     all_fragments_received: bool,
     total_amount_of_frags: u64,
     received_amount_of_frags: u64,
+    fragments_released: bool,
+    last_activity: u64,
+}
+
+/// An inbound session evicted before all of its fragments were received.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbandonedSession {
+    pub session_id: u64,
+    pub sender_id: u8,
+    pub received_amount_of_frags: u64,
+    pub total_amount_of_frags: u64,
+    /// Routing header of one of the received fragments.
+    pub routing_header: Option<SourceRoutingHeader>,
 }
 
 impl PacketStore {
-    fn new(total_amount_of_frags: u64) -> Self {
+    fn new(total_amount_of_frags: u64, now: u64) -> Self {
         PacketStore {
             packets: HashMap::new(),
             all_fragments_received: false,
             total_amount_of_frags,
             received_amount_of_frags: 0,
+            fragments_released: false,
+            last_activity: now,
         }
     }
 }
 
 impl Database {
+    /// Saves a message and indexes it to the conversation with its peer.
+    ///
+    /// Outbound messages are timestamped as sent and inbound messages as
+    /// received. A response is linked to the oldest unanswered request
+    /// exchanged with the same peer.
     pub fn save_message(&mut self, message: &Message) {
         let message_id = MessageID(SessionID(message.session_id), SenderID(message.source));
-        self.messages.insert(message_id, message.clone());
+        if self.messages.insert(message_id, message.clone()).is_some() {
+            return;
+        }
+
+        let peer = self.get_peer(message);
+        let now = self.clock.now_millis();
+        let outbound = message.source == self.node_id;
+        let mut meta = MessageMeta {
+            peer,
+            sent_at: outbound.then_some(now),
+            received_at: (!outbound).then_some(now),
+            in_reply_to: None,
+            replied_by: None,
+            unverified: false,
+        };
+
+        if matches!(message.content, MessageType::Request(_)) {
+            self.pending_requests
+                .entry(peer)
+                .or_default()
+                .push_back(message_id);
+        } else if let Some(request_id) = self.take_pending_request(peer, message_id.1) {
+            meta.in_reply_to = Some(request_id);
+            if let Some(request_meta) = self.message_meta.get_mut(&request_id) {
+                request_meta.replied_by = Some(message_id);
+            }
+        }
+
+        self.message_meta.insert(message_id, meta);
+        self.message_order.push(message_id);
+        self.conversations.entry(peer).or_default().push(message_id);
+        self.enforce_message_retention();
+    }
+
+    /// Removes and returns the oldest unanswered request exchanged with `peer`
+    /// that was not sent by `responder`.
+    fn take_pending_request(&mut self, peer: u8, responder: SenderID) -> Option<MessageID> {
+        let pending = self.pending_requests.get_mut(&peer)?;
+        let index = pending
+            .iter()
+            .position(|request_id| request_id.1 != responder)?;
+        pending.remove(index)
+    }
+
+    pub fn get_message_meta(&self, message_id: MessageID) -> Option<MessageMeta> {
+        self.message_meta.get(&message_id).copied()
+    }
+
+    /// Marks a message whose sender could not be authenticated.
+    pub fn flag_message_unverified(&mut self, message_id: MessageID) {
+        if let Some(meta) = self.message_meta.get_mut(&message_id) {
+            meta.unverified = true;
+        }
+    }
+
+    /// Returns IDs of all messages exchanged with `peer` ordered by arrival.
+    pub fn get_conversation(&self, peer: u8) -> Vec<MessageID> {
+        self.get_message_ids_by_peer(Some(peer)).to_vec()
     }
 
     pub fn save_packet(&mut self, packet: Packet) -> Result<()> {
@@ -68,14 +191,27 @@ impl Database {
         let fragment_id = fragment.fragment_index;
         let packet_id = PacketID2(session_id, sender_id);
         let amount_of_frags = fragment.total_n_fragments;
+        let now = self.clock.now_millis();
+
+        // Late fragments of an evicted message must not deliver it again.
+        if self.evicted_sessions.contains(&packet_id) {
+            return Ok(());
+        }
 
         let packet_store = self
             .packets
             .entry(packet_id)
-            .or_insert(PacketStore::new(amount_of_frags));
+            .or_insert_with(|| PacketStore::new(amount_of_frags, now));
 
-        packet_store.packets.insert(fragment_id, packet);
-        packet_store.received_amount_of_frags += 1;
+        // Fragments of a released session have already been handled.
+        if packet_store.fragments_released {
+            return Ok(());
+        }
+        packet_store.last_activity = now;
+        // Duplicates replace the stored fragment but are not counted twice.
+        if packet_store.packets.insert(fragment_id, packet).is_none() {
+            packet_store.received_amount_of_frags += 1;
+        }
         if packet_store.received_amount_of_frags == packet_store.total_amount_of_frags {
             packet_store.all_fragments_received = true;
         }
@@ -86,18 +222,18 @@ impl Database {
         self.messages.get(&message_id).cloned()
     }
 
-    fn is_message_sent_to_sc(&self, message_id: MessageID) -> bool {
+    pub fn is_message_sent_to_sc(&self, message_id: MessageID) -> bool {
         self.messages_sent_to_sc.contains(&message_id)
     }
 
-    fn is_message_read(&self, message_id: MessageID) -> bool {
+    pub fn is_message_read(&self, message_id: MessageID) -> bool {
         self.messages_read.contains(&message_id)
     }
     fn is_packet_ack_received(&self, packet_id: PacketID) -> bool {
         self.packets_received_ack.contains(&packet_id)
     }
 
-    fn is_packet_sent_to_sc(&self, packet_id: PacketID) -> bool {
+    pub fn is_packet_sent_to_sc(&self, packet_id: PacketID) -> bool {
         self.packets_sent_to_sc.contains(&packet_id)
     }
 
@@ -110,7 +246,7 @@ impl Database {
         }
     }
 
-    fn update_message_to_read(&mut self, message_id: MessageID) -> Result<()> {
+    pub fn update_message_to_read(&mut self, message_id: MessageID) -> Result<()> {
         if self.messages.contains_key(&message_id) {
             self.messages_read.insert(message_id);
             Ok(())
@@ -121,7 +257,18 @@ impl Database {
         }
     }
 
-    fn update_message_sent_to_simulation_controller(
+    pub fn update_message_to_unread(&mut self, message_id: MessageID) -> Result<()> {
+        if self.messages.contains_key(&message_id) {
+            self.messages_read.remove(&message_id);
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Tried to update message unread status but there is no such message!"
+            ))
+        }
+    }
+
+    pub fn update_message_sent_to_simulation_controller(
         &mut self,
         message_id: MessageID,
     ) -> Result<()> {
@@ -135,7 +282,10 @@ impl Database {
         }
     }
 
-    fn update_packet_sent_to_simulation_controller(&mut self, packet_id: PacketID) -> Result<()> {
+    pub fn update_packet_sent_to_simulation_controller(
+        &mut self,
+        packet_id: PacketID,
+    ) -> Result<()> {
         let session_id = PacketID2(packet_id.0, packet_id.1);
         if self.packets.contains_key(&session_id) {
             let packet_id = PacketID(packet_id.0, packet_id.1, packet_id.2);
@@ -154,8 +304,13 @@ impl Database {
         let session_id = &PacketID2(session_id, sender_id);
 
         if let Some(packet_store) = self.packets.get(session_id) {
-            if packet_store.packets.contains_key(&packet_id.2.0) {
-                self.packets_received_ack.insert(packet_id);
+            if packet_store.fragments_released {
+                // Every fragment was already ACKed or handled.
+                Ok(())
+            } else if packet_store.packets.contains_key(&packet_id.2.0) {
+                if self.packets_received_ack.insert(packet_id) {
+                    self.record_fragment_acked(*session_id);
+                }
                 Ok(())
             } else {
                 Err(anyhow!(
@@ -169,24 +324,120 @@ impl Database {
         }
     }
 
-    pub fn get_undread_message_ids_from_server(&mut self, node_id: u8) -> Option<Vec<MessageID>> {
-        let mut unread_messages: Vec<MessageID> = vec![];
-        let read_messages = self.messages_read.clone();
-        let read_messages: Vec<&MessageID> = read_messages.iter().collect();
-        let all_messages: Vec<&MessageID> = self.messages.keys().collect();
+    /// Returns the node this client is talking with in the given message.
+    fn get_peer(&self, message: &Message) -> u8 {
+        if message.source == self.node_id {
+            message.destination
+        } else {
+            message.source
+        }
+    }
 
-        for message in all_messages {
-            if !read_messages.contains(&message) && message.1 != SenderID(node_id) {
-                unread_messages.push(*message);
-                self.messages_read.insert(*message);
-            }
+    /// Returns IDs of messages exchanged with `peer`, or all messages if `peer`
+    /// is `None`, in the order they were stored.
+    fn get_message_ids_by_peer(&self, peer: Option<u8>) -> &[MessageID] {
+        match peer {
+            Some(peer) => self
+                .conversations
+                .get(&peer)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+            None => &self.message_order,
         }
+    }
 
-        if unread_messages.is_empty() {
-            None
-        } else {
-            Some(unread_messages)
+    /// Returns a page of message IDs exchanged with `peer` ordered by arrival,
+    /// together with the total amount of such messages.
+    pub fn list_message_ids(
+        &self,
+        peer: Option<u8>,
+        offset: usize,
+        limit: usize,
+    ) -> (Vec<MessageID>, usize) {
+        let message_ids = self.get_message_ids_by_peer(peer);
+        let total = message_ids.len();
+        let page = message_ids
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .copied()
+            .collect();
+        (page, total)
+    }
+
+    /// Returns IDs of unread messages received from `peer`, or from any node
+    /// if `peer` is `None`, ordered by arrival. Read status is not changed.
+    pub fn get_unread_message_ids(&self, peer: Option<u8>) -> Vec<MessageID> {
+        self.get_message_ids_by_peer(peer)
+            .iter()
+            .filter(|message_id| {
+                message_id.1 != SenderID(self.node_id) && !self.is_message_read(**message_id)
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Returns the amount of unread received messages per peer, ordered by peer ID.
+    pub fn get_unread_counts(&self) -> Vec<(u8, usize)> {
+        let mut counts: BTreeMap<u8, usize> = BTreeMap::new();
+        for message_id in self.get_unread_message_ids(None) {
+            if let Some(meta) = self.message_meta.get(&message_id) {
+                *counts.entry(meta.peer).or_default() += 1;
+            }
         }
+        counts.into_iter().collect()
+    }
+
+    /// Evicts inbound sessions which have not received a fragment within
+    /// `timeout` and are still missing fragments.
+    pub fn evict_stale_sessions(&mut self, timeout: Duration) -> Vec<AbandonedSession> {
+        let timeout = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+        let now = self.clock.now_millis();
+        let stale_sessions: Vec<PacketID2> = self
+            .packets
+            .iter()
+            .filter(|(session, packet_store)| {
+                session.1 != SenderID(self.node_id)
+                    && !packet_store.all_fragments_received
+                    && !packet_store.fragments_released
+                    && now.saturating_sub(packet_store.last_activity) >= timeout
+            })
+            .map(|(session, _)| *session)
+            .collect();
+
+        stale_sessions
+            .into_iter()
+            .filter_map(|session| {
+                self.record_session_abandoned(session);
+                self.remove_session(session)
+            })
+            .collect()
+    }
+
+    /// Drops every fragment received in a session, e.g. because the
+    /// reassembled message turned out to be corrupted. Fragments arriving
+    /// later start the session anew.
+    pub fn discard_session(&mut self, session_id: u64, sender_id: u8) -> Option<AbandonedSession> {
+        let session = PacketID2(SessionID(session_id), SenderID(sender_id));
+        let outside_session =
+            |packet_id: &PacketID| packet_id.0 != session.0 || packet_id.1 != session.1;
+        self.packets_sent_to_sc.retain(outside_session);
+        self.remove_session(session)
+    }
+
+    fn remove_session(&mut self, session: PacketID2) -> Option<AbandonedSession> {
+        let packet_store = self.packets.remove(&session)?;
+        Some(AbandonedSession {
+            session_id: session.0.0,
+            sender_id: session.1.0,
+            received_amount_of_frags: packet_store.received_amount_of_frags,
+            total_amount_of_frags: packet_store.total_amount_of_frags,
+            routing_header: packet_store
+                .packets
+                .values()
+                .next()
+                .map(|packet| packet.routing_header.clone()),
+        })
     }
 
     pub fn get_amount_of_fragments_received(&self, session_id: u64, sender_id: u8) -> Option<u64> {
@@ -234,22 +485,41 @@ impl Database {
 mod tests {
     #![allow(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
     // #[cfg(test)]
-    use messages::{Message, MessageType, RequestType, TextRequest};
+    use messages::{Message, MessageType, RequestType, ResponseType, TextRequest, TextResponse};
     #[allow(unused_imports)]
     use pretty_assertions::{assert_eq, assert_ne};
-    use rand::{Rng, rng};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::cell::RefCell;
+    use std::sync::Arc;
+    use std::time::Duration;
     use wg_2024::{
         network::SourceRoutingHeader,
         packet::{Ack, Fragment, Packet, PacketType},
     };
 
+    use crate::clock::ManualClock;
     use crate::database::{
         Database, MessageID, SenderID, SessionID,
         packet::{FragmentID, PacketID},
+        retention::RetentionPolicy,
+        session::{MAX_ABANDONED_SESSION_TRACES, MAX_FINISHED_SESSION_TRACES, SessionDirection},
+        snapshot::DatabaseSnapshot,
     };
 
+    const NODE_ID: u8 = 2;
+
+    thread_local! {
+        // Every test runs on its own thread and draws the same sequence.
+        static RNG: RefCell<StdRng> = RefCell::new(StdRng::seed_from_u64(0x5EED));
+    }
+
+    fn random_session_id() -> u64 {
+        RNG.with(|rng| rng.borrow_mut().random_range(1..u64::MAX))
+    }
+
     fn get_msg_with_random_session_id() -> Message {
-        let session_id = rng().random_range(1..u64::MAX);
+        let session_id = random_session_id();
         Message {
             source: 1,
             destination: 2,
@@ -261,7 +531,7 @@ mod tests {
     }
 
     fn get_fragment_packet_with_random_session_id() -> Packet {
-        let session_id = rng().random_range(1..u64::MAX);
+        let session_id = random_session_id();
 
         let hops = [3, 5, 6, 7, 4];
 
@@ -286,7 +556,7 @@ mod tests {
 
     #[allow(dead_code)]
     fn get_two_fragment_packets_with_random_session_id() -> Vec<Packet> {
-        let session_id = rng().random_range(1..u64::MAX);
+        let session_id = random_session_id();
         let hops = [3, 5, 6, 7, 4];
         let mut packets = vec![];
 
@@ -314,7 +584,7 @@ mod tests {
     #[test]
     fn test_save_message() {
         // message to be saved
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
         let original_message = get_msg_with_random_session_id();
         db.save_message(&original_message);
         let message = db.get_message(MessageID(
@@ -326,7 +596,7 @@ mod tests {
 
     #[test]
     fn test_get_message() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
         let message = get_msg_with_random_session_id();
         db.save_message(&message);
         let session_id = message.session_id;
@@ -339,7 +609,7 @@ mod tests {
 
     #[test]
     fn test_set_message_to_read() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
 
         let test_message = get_msg_with_random_session_id();
         let message_id = MessageID(
@@ -365,7 +635,7 @@ mod tests {
 
     #[test]
     fn test_set_message_to_sent_to_sc() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
 
         let test_message = get_msg_with_random_session_id();
         let message_id = MessageID(
@@ -392,7 +662,7 @@ mod tests {
 
     #[test]
     fn test_save_packet() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
 
         // packet to be saved
         let original_packet = get_fragment_packet_with_random_session_id();
@@ -408,7 +678,7 @@ mod tests {
     #[should_panic(expected = "Packet is not Fragment!")]
     #[test]
     fn test_save_wrong_type_packet() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
 
         let mut original_packet = get_fragment_packet_with_random_session_id();
         original_packet.pack_type = PacketType::Ack(Ack { fragment_index: 1 });
@@ -417,7 +687,7 @@ mod tests {
 
     #[test]
     fn test_set_packet_to_sent_to_sc() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
 
         let packet = get_fragment_packet_with_random_session_id();
         let session_id = SessionID(packet.session_id);
@@ -441,7 +711,7 @@ mod tests {
 
     #[test]
     fn test_set_packet_to_ack_received() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
 
         let packet = get_fragment_packet_with_random_session_id();
         let session_id = SessionID(packet.session_id);
@@ -464,7 +734,7 @@ mod tests {
 
     #[test]
     fn test_getting_amount_of_frags_received() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
 
         let packets = get_two_fragment_packets_with_random_session_id();
         let session_id = packets[0].session_id;
@@ -487,7 +757,7 @@ mod tests {
 
     #[test]
     fn test_getting_frags_for_a_session() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
 
         let packets = get_two_fragment_packets_with_random_session_id();
         let session_id = packets[0].session_id;
@@ -507,7 +777,7 @@ mod tests {
 
     #[test]
     fn test_are_all_acks_received() {
-        let mut db = Database::new();
+        let mut db = Database::new(NODE_ID);
 
         let packets = get_two_fragment_packets_with_random_session_id();
         let session_id = packets[0].session_id;
@@ -542,4 +812,601 @@ mod tests {
             .unwrap();
         assert!(succssfully_sent);
     }
+
+    fn get_msg_from(source: u8, session_id: u64) -> Message {
+        Message {
+            source,
+            destination: NODE_ID,
+            session_id,
+            content: MessageType::Request(RequestType::TextRequest(TextRequest::Text(
+                "Hello".to_string(),
+            ))),
+        }
+    }
+
+    #[test]
+    fn test_unread_messages_are_not_marked_read_when_queried() {
+        let mut db = Database::new(NODE_ID);
+        let message = get_msg_from(1, 10);
+        db.save_message(&message);
+        let message_id = MessageID(SessionID(10), SenderID(1));
+
+        assert_eq!(db.get_unread_message_ids(None), vec![message_id]);
+        assert_eq!(db.get_unread_message_ids(None), vec![message_id]);
+
+        db.update_message_to_read(message_id).unwrap();
+        assert!(db.get_unread_message_ids(None).is_empty());
+
+        db.update_message_to_unread(message_id).unwrap();
+        assert_eq!(db.get_unread_message_ids(None), vec![message_id]);
+    }
+
+    #[test]
+    fn test_unread_messages_filtered_by_peer_in_arrival_order() {
+        let mut db = Database::new(NODE_ID);
+        for (source, session_id) in [(5, 30), (1, 20), (5, 10), (NODE_ID, 40)] {
+            db.save_message(&get_msg_from(source, session_id));
+        }
+
+        assert_eq!(
+            db.get_unread_message_ids(Some(5)),
+            vec![
+                MessageID(SessionID(30), SenderID(5)),
+                MessageID(SessionID(10), SenderID(5)),
+            ]
+        );
+        assert_eq!(db.get_unread_message_ids(None).len(), 3);
+        assert_eq!(db.get_unread_counts(), vec![(1, 1), (5, 2)]);
+    }
+
+    #[test]
+    fn test_list_message_ids_paging() {
+        let mut db = Database::new(NODE_ID);
+        for session_id in 0..5 {
+            db.save_message(&get_msg_from(1, session_id));
+        }
+
+        let (page, total) = db.list_message_ids(Some(1), 3, 10);
+        assert_eq!(total, 5);
+        assert_eq!(
+            page,
+            vec![
+                MessageID(SessionID(3), SenderID(1)),
+                MessageID(SessionID(4), SenderID(1)),
+            ]
+        );
+
+        let (page, total) = db.list_message_ids(Some(7), 0, 10);
+        assert_eq!(total, 0);
+        assert!(page.is_empty());
+    }
+
+    #[test]
+    fn test_conversation_index_and_timestamps() {
+        let mut db = Database::new(NODE_ID);
+        let mut outbound = get_msg_from(NODE_ID, 1);
+        outbound.destination = 5;
+        db.save_message(&outbound);
+        db.save_message(&get_msg_from(5, 1));
+        db.save_message(&get_msg_from(6, 1));
+
+        let conversation = db.get_conversation(5);
+        assert_eq!(
+            conversation,
+            vec![
+                MessageID(SessionID(1), SenderID(NODE_ID)),
+                MessageID(SessionID(1), SenderID(5)),
+            ]
+        );
+
+        let sent_meta = db.get_message_meta(conversation[0]).unwrap();
+        assert_eq!(sent_meta.peer, 5);
+        assert!(sent_meta.sent_at.is_some());
+        assert!(sent_meta.received_at.is_none());
+
+        let received_meta = db.get_message_meta(conversation[1]).unwrap();
+        assert_eq!(received_meta.peer, 5);
+        assert!(received_meta.sent_at.is_none());
+        assert!(received_meta.received_at.is_some());
+
+        assert!(db.get_conversation(7).is_empty());
+    }
+
+    fn get_response_from(source: u8, session_id: u64) -> Message {
+        Message {
+            source,
+            destination: NODE_ID,
+            session_id,
+            content: MessageType::Response(ResponseType::TextResponse(TextResponse::Text(
+                "Hello back".to_string(),
+            ))),
+        }
+    }
+
+    #[test]
+    fn test_responses_are_linked_to_requests_in_order() {
+        let mut db = Database::new(NODE_ID);
+        for session_id in [1, 2] {
+            let mut request = get_msg_from(NODE_ID, session_id);
+            request.destination = 5;
+            db.save_message(&request);
+        }
+        // A request of another peer is not answered by peer 5.
+        let mut other_request = get_msg_from(NODE_ID, 3);
+        other_request.destination = 6;
+        db.save_message(&other_request);
+
+        db.save_message(&get_response_from(5, 10));
+        db.save_message(&get_response_from(5, 11));
+
+        let first_request = MessageID(SessionID(1), SenderID(NODE_ID));
+        let second_request = MessageID(SessionID(2), SenderID(NODE_ID));
+        let first_response = MessageID(SessionID(10), SenderID(5));
+        let second_response = MessageID(SessionID(11), SenderID(5));
+        let meta = |id| db.get_message_meta(id).unwrap();
+
+        assert_eq!(meta(first_request).replied_by, Some(first_response));
+        assert_eq!(meta(first_response).in_reply_to, Some(first_request));
+        assert_eq!(meta(second_request).replied_by, Some(second_response));
+        assert_eq!(meta(second_response).in_reply_to, Some(second_request));
+        assert_eq!(
+            meta(MessageID(SessionID(3), SenderID(NODE_ID))).replied_by,
+            None
+        );
+
+        // Nothing is left to answer, so a further response stays unlinked.
+        db.save_message(&get_response_from(5, 12));
+        assert_eq!(
+            db.get_message_meta(MessageID(SessionID(12), SenderID(5)))
+                .unwrap()
+                .in_reply_to,
+            None
+        );
+    }
+
+    #[test]
+    fn test_take_pending_request_skips_own_requests() {
+        let mut db = Database::new(NODE_ID);
+        let mut outbound = get_msg_from(NODE_ID, 1);
+        outbound.destination = 5;
+        db.save_message(&outbound);
+        db.save_message(&get_msg_from(5, 4));
+
+        let outbound_id = MessageID(SessionID(1), SenderID(NODE_ID));
+        let inbound_id = MessageID(SessionID(4), SenderID(5));
+        // A node never answers its own request.
+        assert_eq!(db.take_pending_request(5, SenderID(5)), Some(outbound_id));
+        assert_eq!(
+            db.take_pending_request(5, SenderID(NODE_ID)),
+            Some(inbound_id)
+        );
+        assert_eq!(db.take_pending_request(5, SenderID(5)), None);
+        assert_eq!(db.take_pending_request(7, SenderID(7)), None);
+    }
+
+    #[test]
+    fn test_fragments_released_after_reassembly() {
+        let retention = RetentionPolicy {
+            drop_fragments_after_reassembly: true,
+            ..RetentionPolicy::default()
+        };
+        let mut db = Database::with_retention_policy(NODE_ID, retention);
+
+        let packets = get_two_fragment_packets_with_random_session_id();
+        let session_id = packets[0].session_id;
+        let sender_id = packets[0].routing_header.hops[0];
+        db.save_packet(packets[0].clone()).unwrap();
+        db.save_packet(packets[1].clone()).unwrap();
+        assert_eq!(db.get_memory_stats().stored_fragments, 2);
+
+        db.message_reassembled(session_id, sender_id);
+        assert_eq!(db.get_memory_stats().stored_fragments, 0);
+        assert_eq!(db.get_memory_stats().packet_stores, 1);
+
+        // A late duplicate is neither stored nor counted.
+        db.save_packet(packets[1].clone()).unwrap();
+        assert_eq!(db.get_memory_stats().stored_fragments, 0);
+        assert_eq!(
+            db.get_amount_of_fragments_received(session_id, sender_id),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_duplicate_fragments_are_counted_once() {
+        let mut db = Database::new(NODE_ID);
+        let packet = get_fragment_packet_with_random_session_id();
+        let session_id = packet.session_id;
+        let sender_id = packet.routing_header.hops[0];
+
+        db.save_packet(packet.clone()).unwrap();
+        db.save_packet(packet).unwrap();
+        assert_eq!(
+            db.get_amount_of_fragments_received(session_id, sender_id),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_message_history_capped_by_count() {
+        let retention = RetentionPolicy {
+            max_messages: Some(2),
+            ..RetentionPolicy::default()
+        };
+        let mut db = Database::with_retention_policy(NODE_ID, retention);
+        for session_id in 0..3 {
+            db.save_message(&get_msg_from(1, session_id));
+        }
+
+        assert_eq!(db.get_memory_stats().messages, 2);
+        assert!(
+            db.get_message(MessageID(SessionID(0), SenderID(1)))
+                .is_none()
+        );
+        assert_eq!(
+            db.get_conversation(1),
+            vec![
+                MessageID(SessionID(1), SenderID(1)),
+                MessageID(SessionID(2), SenderID(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_message_retention_keeps_sessions_in_flight() {
+        let retention = RetentionPolicy {
+            max_messages: Some(1),
+            ..RetentionPolicy::default()
+        };
+        let mut db = Database::with_retention_policy(3, retention);
+        let packets = get_two_fragment_packets_with_random_session_id();
+        let session_id = packets[0].session_id;
+        let mut outbound = get_msg_from(3, session_id);
+        outbound.destination = 4;
+        db.save_message(&outbound);
+        for packet in &packets {
+            db.save_packet(packet.clone()).unwrap();
+        }
+        db.save_message(&get_msg_from(1, 0));
+        db.save_message(&get_msg_from(1, 1));
+
+        // The unACKed message outlives newer messages.
+        let outbound_id = MessageID(SessionID(session_id), SenderID(3));
+        assert!(db.get_message(outbound_id).is_some());
+        assert!(db.get_amount_of_fragments_received(session_id, 3).is_some());
+        assert_eq!(db.get_memory_stats().messages, 2);
+
+        for fragment_index in 0..=1 {
+            db.update_packet_ack_received(PacketID(
+                SessionID(session_id),
+                SenderID(3),
+                FragmentID(fragment_index),
+            ))
+            .unwrap();
+        }
+        db.enforce_message_retention();
+        assert!(db.get_message(outbound_id).is_none());
+        assert!(db.get_amount_of_fragments_received(session_id, 3).is_none());
+        assert_eq!(
+            db.get_conversation(1),
+            vec![MessageID(SessionID(1), SenderID(1))]
+        );
+    }
+
+    #[test]
+    fn test_late_fragments_of_evicted_messages_are_dropped() {
+        let retention = RetentionPolicy {
+            max_messages: Some(1),
+            ..RetentionPolicy::default()
+        };
+        let mut db = Database::with_retention_policy(NODE_ID, retention);
+        let packet = get_fragment_packet_with_random_session_id();
+        let session_id = packet.session_id;
+        db.save_packet(packet.clone()).unwrap();
+        db.save_message(&get_msg_from(3, session_id));
+        db.save_message(&get_msg_from(1, 0));
+        assert!(db.is_session_evicted(session_id, 3));
+
+        // A retransmitted fragment neither starts a new session nor is stored.
+        db.save_packet(packet).unwrap();
+        assert!(db.get_amount_of_fragments_received(session_id, 3).is_none());
+        assert_eq!(db.get_memory_stats().packet_stores, 0);
+    }
+
+    #[test]
+    fn test_evict_stale_sessions() {
+        let mut db = Database::new(NODE_ID);
+
+        let packets = get_two_fragment_packets_with_random_session_id();
+        let session_id = packets[0].session_id;
+        let sender_id = packets[0].routing_header.hops[0];
+        db.save_packet(packets[0].clone()).unwrap();
+        let complete_packet = get_fragment_packet_with_random_session_id();
+        db.save_packet(complete_packet.clone()).unwrap();
+
+        assert!(db.evict_stale_sessions(Duration::from_secs(60)).is_empty());
+
+        let abandoned = db.evict_stale_sessions(Duration::ZERO);
+        assert_eq!(abandoned.len(), 1);
+        assert_eq!(abandoned[0].session_id, session_id);
+        assert_eq!(abandoned[0].sender_id, sender_id);
+        assert_eq!(abandoned[0].received_amount_of_frags, 1);
+        assert_eq!(abandoned[0].total_amount_of_frags, 2);
+        assert_eq!(
+            abandoned[0].routing_header,
+            Some(packets[0].routing_header.clone())
+        );
+
+        assert!(
+            db.get_amount_of_fragments_received(session_id, sender_id)
+                .is_none()
+        );
+        assert!(
+            db.get_amount_of_fragments_received(
+                complete_packet.session_id,
+                complete_packet.routing_header.hops[0]
+            )
+            .is_some()
+        );
+    }
+
+    #[test]
+    fn test_evict_stale_sessions_after_clock_advances() {
+        let clock = ManualClock::new(1_000);
+        let mut db = Database::new(NODE_ID);
+        db.set_clock(Arc::new(clock.clone()));
+
+        let packets = get_two_fragment_packets_with_random_session_id();
+        db.save_packet(packets[0].clone()).unwrap();
+
+        clock.advance(Duration::from_secs(29));
+        assert!(db.evict_stale_sessions(Duration::from_secs(30)).is_empty());
+
+        // New fragments restart the timeout.
+        db.save_packet(packets[0].clone()).unwrap();
+        clock.advance(Duration::from_secs(29));
+        assert!(db.evict_stale_sessions(Duration::from_secs(30)).is_empty());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(db.evict_stale_sessions(Duration::from_secs(30)).len(), 1);
+    }
+
+    #[test]
+    fn test_message_history_capped_by_age() {
+        let retention = RetentionPolicy {
+            max_message_age: Some(Duration::from_secs(60)),
+            ..RetentionPolicy::default()
+        };
+        let clock = ManualClock::new(1_000);
+        let mut db = Database::with_retention_policy(NODE_ID, retention);
+        db.set_clock(Arc::new(clock.clone()));
+
+        db.save_message(&get_msg_from(1, 0));
+        clock.advance(Duration::from_secs(30));
+        db.save_message(&get_msg_from(1, 1));
+        clock.advance(Duration::from_secs(31));
+        db.enforce_message_retention();
+
+        assert_eq!(
+            db.get_conversation(1),
+            vec![MessageID(SessionID(1), SenderID(1))]
+        );
+    }
+
+    #[test]
+    fn test_flag_message_unverified() {
+        let mut db = Database::new(NODE_ID);
+        db.save_message(&get_msg_from(1, 10));
+        let message_id = MessageID(SessionID(10), SenderID(1));
+        assert!(!db.get_message_meta(message_id).unwrap().unverified);
+
+        db.flag_message_unverified(message_id);
+        assert!(db.get_message_meta(message_id).unwrap().unverified);
+    }
+
+    #[test]
+    fn test_discard_session() {
+        let mut db = Database::new(NODE_ID);
+
+        let packets = get_two_fragment_packets_with_random_session_id();
+        let session_id = packets[0].session_id;
+        let sender_id = packets[0].routing_header.hops[0];
+        for packet in &packets {
+            db.save_packet(packet.clone()).unwrap();
+        }
+
+        let discarded = db.discard_session(session_id, sender_id).unwrap();
+        assert_eq!(discarded.received_amount_of_frags, 2);
+        assert_eq!(discarded.total_amount_of_frags, 2);
+        assert!(db.get_packets_for_session(session_id, sender_id).is_none());
+        assert!(db.discard_session(session_id, sender_id).is_none());
+
+        // A retransmitted fragment starts the session anew.
+        db.save_packet(packets[0].clone()).unwrap();
+        assert_eq!(
+            db.get_amount_of_fragments_received(session_id, sender_id),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_snapshot_json_round_trip() {
+        let mut db = Database::new(NODE_ID);
+        let message = get_msg_from(1, 10);
+        let message_id = MessageID(SessionID(10), SenderID(1));
+        db.save_message(&message);
+        db.update_message_to_read(message_id).unwrap();
+        db.update_message_sent_to_simulation_controller(message_id)
+            .unwrap();
+
+        let packets = get_two_fragment_packets_with_random_session_id();
+        let session_id = packets[0].session_id;
+        let sender_id = packets[0].routing_header.hops[0];
+        let packet_id = PacketID(SessionID(session_id), SenderID(sender_id), FragmentID(1));
+        db.save_packet(packets[0].clone()).unwrap();
+        db.save_packet(packets[1].clone()).unwrap();
+        db.update_packet_ack_received(packet_id).unwrap();
+        db.update_packet_sent_to_simulation_controller(packet_id)
+            .unwrap();
+
+        let json = db.export_snapshot().to_json().unwrap();
+        let snapshot = DatabaseSnapshot::from_json(&json).unwrap();
+        let restored = Database::from_snapshot(snapshot, RetentionPolicy::default()).unwrap();
+
+        assert_eq!(restored.get_message(message_id), Some(message));
+        assert_eq!(
+            restored.get_message_meta(message_id),
+            db.get_message_meta(message_id)
+        );
+        assert!(restored.is_message_read(message_id));
+        assert!(restored.is_message_sent_to_sc(message_id));
+        assert_eq!(restored.get_conversation(1), vec![message_id]);
+
+        assert_eq!(
+            restored.get_amount_of_fragments_received(session_id, sender_id),
+            Some(2)
+        );
+        assert_eq!(restored.get_packet(packet_id), Some(packets[1].clone()));
+        assert!(restored.is_packet_ack_received(packet_id));
+        assert!(restored.is_packet_sent_to_sc(packet_id));
+        assert!(!restored.is_packet_ack_received(PacketID(
+            SessionID(session_id),
+            SenderID(sender_id),
+            FragmentID(0)
+        )));
+    }
+
+    #[test]
+    fn test_snapshot_keeps_session_traces() {
+        let mut db = Database::new(NODE_ID);
+        let packets = get_two_fragment_packets_with_random_session_id();
+        let session_id = packets[0].session_id;
+        let sender_id = packets[0].routing_header.hops[0];
+        db.save_packet(packets[0].clone()).unwrap();
+        db.record_fragment_transfer(&packets[0]);
+
+        let snapshot = db.export_snapshot();
+        assert_eq!(snapshot.sessions[0].total_n_fragments, 2);
+        assert_eq!(snapshot.sessions[0].received_n_fragments, 1);
+        let json = snapshot.to_json().unwrap();
+        let mut restored = Database::from_snapshot(
+            DatabaseSnapshot::from_json(&json).unwrap(),
+            RetentionPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(restored.get_session_statuses(), db.get_session_statuses());
+
+        // Fragments transferred before the snapshot are still recognised.
+        restored.record_fragment_transfer(&packets[0]);
+        restored.record_fragment_transfer(&packets[1]);
+        let status = restored.get_session_status(session_id, sender_id).unwrap();
+        assert_eq!(status.fragments_transferred, 2);
+    }
+
+    #[test]
+    fn test_session_status_of_outbound_session() {
+        let mut db = Database::new(3);
+        let packets = get_two_fragment_packets_with_random_session_id();
+        let session_id = packets[0].session_id;
+        for packet in &packets {
+            db.save_packet(packet.clone()).unwrap();
+            assert!(!db.record_fragment_transfer(packet));
+        }
+
+        // Fragment 1 is NACKed and re-sent through another route.
+        db.record_fragment_nacked(session_id, 3);
+        let mut resent = packets[1].clone();
+        resent.routing_header.hops = vec![3, 8, 4];
+        assert!(db.record_fragment_transfer(&resent));
+
+        for fragment_index in 0..=1 {
+            db.update_packet_ack_received(PacketID(
+                SessionID(session_id),
+                SenderID(3),
+                FragmentID(fragment_index),
+            ))
+            .unwrap();
+        }
+        db.record_session_finished(session_id, 3);
+
+        let status = db.get_session_status(session_id, 3).unwrap();
+        assert_eq!(status.direction, SessionDirection::Outbound);
+        assert_eq!(status.total_fragments, 2);
+        assert_eq!(status.fragments_transferred, 2);
+        assert_eq!(status.fragments_acked, 2);
+        assert_eq!(status.fragments_nacked, 1);
+        assert_eq!(status.retransmissions, 1);
+        assert_eq!(status.routes, vec![vec![3, 5, 6, 7, 4], vec![3, 8, 4]]);
+        assert!(status.finished_at.is_some());
+        assert_eq!(db.get_session_statuses(), vec![status]);
+    }
+
+    #[test]
+    fn test_session_status_of_abandoned_inbound_session() {
+        let mut db = Database::new(NODE_ID);
+        let packets = get_two_fragment_packets_with_random_session_id();
+        let session_id = packets[0].session_id;
+        db.save_packet(packets[0].clone()).unwrap();
+        db.record_fragment_transfer(&packets[0]);
+        db.evict_stale_sessions(Duration::ZERO);
+
+        let status = db.get_session_status(session_id, 3).unwrap();
+        assert_eq!(status.direction, SessionDirection::Inbound);
+        assert_eq!(status.fragments_transferred, 1);
+        assert_eq!(status.retransmissions, 0);
+        assert!(status.finished_at.is_none());
+        assert!(status.abandoned);
+    }
+
+    #[test]
+    fn test_finished_session_traces_are_bounded() {
+        let clock = ManualClock::new(1_000);
+        let mut db = Database::new(NODE_ID);
+        db.set_clock(Arc::new(clock.clone()));
+        let unfinished = get_two_fragment_packets_with_random_session_id().remove(0);
+        db.record_fragment_transfer(&unfinished);
+        let mut first_session_id = None;
+        for _ in 0..=MAX_FINISHED_SESSION_TRACES {
+            let packet = get_fragment_packet_with_random_session_id();
+            first_session_id.get_or_insert(packet.session_id);
+            db.record_fragment_transfer(&packet);
+            db.record_session_finished(packet.session_id, 3);
+            clock.advance(Duration::from_millis(1));
+        }
+
+        assert_eq!(
+            db.get_session_statuses().len(),
+            MAX_FINISHED_SESSION_TRACES + 1
+        );
+        assert!(
+            db.get_session_status(first_session_id.unwrap(), 3)
+                .is_none()
+        );
+        assert!(db.get_session_status(unfinished.session_id, 3).is_some());
+    }
+
+    #[test]
+    fn test_abandoned_session_traces_are_bounded() {
+        let clock = ManualClock::new(1_000);
+        let mut db = Database::new(NODE_ID);
+        db.set_clock(Arc::new(clock.clone()));
+        let mut first_session_id = None;
+        for _ in 0..=MAX_ABANDONED_SESSION_TRACES {
+            let packet = get_two_fragment_packets_with_random_session_id().remove(0);
+            first_session_id.get_or_insert(packet.session_id);
+            db.save_packet(packet.clone()).unwrap();
+            db.record_fragment_transfer(&packet);
+            db.evict_stale_sessions(Duration::ZERO);
+            clock.advance(Duration::from_millis(1));
+        }
+
+        assert_eq!(
+            db.get_session_statuses().len(),
+            MAX_ABANDONED_SESSION_TRACES
+        );
+        assert!(
+            db.get_session_status(first_session_id.unwrap(), 3)
+                .is_none()
+        );
+    }
 }
@@ -26,7 +26,7 @@ impl fmt::Display for FragmentID {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub(in crate::database) struct DatabasePacket {
+pub struct DatabasePacket {
     pub packet_id: String,
     pub routing_header_hop_index: usize,
     pub routing_header_hops: Vec<NodeId>,
@@ -39,3 +39,13 @@ pub(in crate::database) struct DatabasePacket {
     pub sent_to_sc: bool,
     pub ack_received: bool,
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseSession {
+    pub session_id: String,
+    pub sender_id: u8,
+    pub total_n_fragments: u64,
+    pub received_n_fragments: u64,
+    pub all_fragments_received: bool,
+    pub fragments_released: bool,
+}
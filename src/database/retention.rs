@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::time::Duration;
+
+use messages::Message;
+
+use super::message::{MessageID, MessageMeta, SenderID, SessionID};
+use super::packet::{PacketID, PacketID2};
+use super::{Database, Packet};
+
+/// Amount of evicted inbound sessions whose late fragments are recognised.
+const MAX_EVICTED_SESSIONS: usize = 1024;
+
+/// Rules for discarding data the `Database` no longer needs.
+///
+/// The default policy keeps everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Drop raw fragments of a received message once it has been reassembled.
+    pub drop_fragments_after_reassembly: bool,
+    /// Drop raw fragments of a sent message once every fragment has been ACKed.
+    pub drop_fragments_after_ack: bool,
+    /// Maximum amount of stored messages. The oldest messages are dropped first.
+    pub max_messages: Option<usize>,
+    /// Maximum age of stored messages.
+    pub max_message_age: Option<Duration>,
+}
+
+/// Amount of data held by the `Database`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub messages: usize,
+    pub packet_stores: usize,
+    pub stored_fragments: usize,
+    pub read_messages: usize,
+    pub messages_sent_to_sc: usize,
+    pub packets_sent_to_sc: usize,
+    pub packets_received_ack: usize,
+    /// Estimate of the memory used, not counting heap data owned by messages.
+    pub approximate_bytes: usize,
+}
+
+impl Database {
+    pub fn get_retention_policy(&self) -> RetentionPolicy {
+        self.retention
+    }
+
+    /// Called once a received message has been reassembled from its fragments.
+    pub fn message_reassembled(&mut self, session_id: u64, sender_id: u8) {
+        if self.retention.drop_fragments_after_reassembly {
+            self.release_fragments(PacketID2(SessionID(session_id), SenderID(sender_id)));
+        }
+    }
+
+    /// Called once every fragment of a sent message has been ACKed.
+    pub fn message_acknowledged(&mut self, session_id: u64, sender_id: u8) {
+        if self.retention.drop_fragments_after_ack {
+            self.release_fragments(PacketID2(SessionID(session_id), SenderID(sender_id)));
+        }
+    }
+
+    /// Drops raw fragments of a session while keeping its bookkeeping, so
+    /// late duplicates are still recognised.
+    fn release_fragments(&mut self, session: PacketID2) {
+        if let Some(packet_store) = self.packets.get_mut(&session) {
+            packet_store.packets.clear();
+            packet_store.fragments_released = true;
+        }
+        let outside_session =
+            |packet_id: &PacketID| packet_id.0 != session.0 || packet_id.1 != session.1;
+        self.packets_received_ack.retain(outside_session);
+        self.packets_sent_to_sc.retain(outside_session);
+    }
+
+    /// Drops messages exceeding the configured amount or age limits,
+    /// oldest first, together with their fragments.
+    ///
+    /// Messages whose session is still in flight are kept until a later
+    /// call, even if that exceeds the limits in the meantime.
+    pub fn enforce_message_retention(&mut self) {
+        let mut expired = HashSet::new();
+        if let Some(max_age) = self.retention.max_message_age {
+            let max_age = u64::try_from(max_age.as_millis()).unwrap_or(u64::MAX);
+            let oldest_allowed = self.clock.now_millis().saturating_sub(max_age);
+            for message_id in &self.message_order {
+                let timestamp = self
+                    .message_meta
+                    .get(message_id)
+                    .and_then(|meta| meta.sent_at.or(meta.received_at));
+                if timestamp.is_some_and(|timestamp| timestamp < oldest_allowed) {
+                    expired.insert(*message_id);
+                }
+            }
+        }
+        if let Some(max_messages) = self.retention.max_messages {
+            let remaining = self.message_order.len() - expired.len();
+            if remaining > max_messages {
+                let overflow = self
+                    .message_order
+                    .iter()
+                    .filter(|message_id| !expired.contains(*message_id))
+                    .take(remaining - max_messages)
+                    .copied()
+                    .collect::<Vec<_>>();
+                expired.extend(overflow);
+            }
+        }
+        expired
+            .retain(|message_id| !self.is_session_in_flight(PacketID2(message_id.0, message_id.1)));
+        if !expired.is_empty() {
+            self.remove_messages(&expired);
+        }
+    }
+
+    /// Whether fragments of `session` are still waiting for an ACK or for
+    /// the rest of the message to arrive.
+    fn is_session_in_flight(&self, session: PacketID2) -> bool {
+        let Some(packet_store) = self.packets.get(&session) else {
+            return false;
+        };
+        if session.1 == SenderID(self.node_id) {
+            self.all_packets_successfully_sent(session.0.0, session.1.0) == Some(false)
+        } else {
+            !packet_store.all_fragments_received
+        }
+    }
+
+    /// Removes messages, their bookkeeping and their fragments.
+    fn remove_messages(&mut self, message_ids: &HashSet<MessageID>) {
+        let mut peers = HashSet::new();
+        let mut sessions = HashSet::new();
+        for message_id in message_ids {
+            self.messages.remove(message_id);
+            self.messages_read.remove(message_id);
+            self.messages_sent_to_sc.remove(message_id);
+            if let Some(meta) = self.message_meta.remove(message_id) {
+                peers.insert(meta.peer);
+            }
+            let session = PacketID2(message_id.0, message_id.1);
+            self.packets.remove(&session);
+            self.session_traces.remove(&session);
+            if session.1 != SenderID(self.node_id) {
+                self.remember_evicted_session(session);
+            }
+            sessions.insert(session);
+        }
+        let outside_sessions =
+            |packet_id: &PacketID| !sessions.contains(&PacketID2(packet_id.0, packet_id.1));
+        self.packets_received_ack.retain(outside_sessions);
+        self.packets_sent_to_sc.retain(outside_sessions);
+        self.message_order.retain(|id| !message_ids.contains(id));
+        for peer in peers {
+            if let Some(conversation) = self.conversations.get_mut(&peer) {
+                conversation.retain(|id| !message_ids.contains(id));
+            }
+            if let Some(pending) = self.pending_requests.get_mut(&peer) {
+                pending.retain(|id| !message_ids.contains(id));
+            }
+        }
+    }
+
+    fn remember_evicted_session(&mut self, session: PacketID2) {
+        if !self.evicted_sessions.insert(session) {
+            return;
+        }
+        self.evicted_session_order.push_back(session);
+        let overflow = self
+            .evicted_session_order
+            .len()
+            .saturating_sub(MAX_EVICTED_SESSIONS);
+        for oldest in self.evicted_session_order.drain(..overflow) {
+            self.evicted_sessions.remove(&oldest);
+        }
+    }
+
+    /// Whether the message of an inbound session was removed by retention.
+    pub fn is_session_evicted(&self, session_id: u64, sender_id: u8) -> bool {
+        self.evicted_sessions
+            .contains(&PacketID2(SessionID(session_id), SenderID(sender_id)))
+    }
+
+    pub fn get_memory_stats(&self) -> MemoryStats {
+        let stored_fragments = self
+            .packets
+            .values()
+            .map(|packet_store| packet_store.packets.len())
+            .sum();
+        let approximate_bytes = self.messages.len() * size_of::<(MessageID, Message)>()
+            + self.message_meta.len() * size_of::<(MessageID, MessageMeta)>()
+            + stored_fragments * size_of::<(u64, Packet)>()
+            + (self.messages_read.len() + self.messages_sent_to_sc.len()) * size_of::<MessageID>()
+            + (self.packets_sent_to_sc.len() + self.packets_received_ack.len())
+                * size_of::<PacketID>();
+
+        MemoryStats {
+            messages: self.messages.len(),
+            packet_stores: self.packets.len(),
+            stored_fragments,
+            read_messages: self.messages_read.len(),
+            messages_sent_to_sc: self.messages_sent_to_sc.len(),
+            packets_sent_to_sc: self.packets_sent_to_sc.len(),
+            packets_received_ack: self.packets_received_ack.len(),
+            approximate_bytes,
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use wg_2024::network::NodeId;
+
+use super::message::{SenderID, SessionID};
+use super::packet::PacketID2;
+use super::{Database, Packet, PacketType};
+
+/// Amount of abandoned inbound sessions whose trace is kept for diagnosis.
+/// Older ones are forgotten first.
+pub(super) const MAX_ABANDONED_SESSION_TRACES: usize = 64;
+
+/// Amount of finished sessions whose trace is kept. Older ones are
+/// forgotten first, unfinished sessions are always kept.
+pub(super) const MAX_FINISHED_SESSION_TRACES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionDirection {
+    Outbound,
+    Inbound,
+}
+
+/// Transfer status of a single session, used to diagnose stuck transfers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionStatus {
+    pub session_id: u64,
+    pub sender_id: NodeId,
+    pub direction: SessionDirection,
+    pub total_fragments: u64,
+    /// Distinct fragments sent for outbound and received for inbound sessions.
+    pub fragments_transferred: u64,
+    pub fragments_acked: u64,
+    /// Amount of NACKs received for the session.
+    pub fragments_nacked: u64,
+    pub retransmissions: u64,
+    /// Every distinct route fragments of the session travelled.
+    pub routes: Vec<Vec<NodeId>>,
+    /// Milliseconds since UNIX epoch.
+    pub started_at: u64,
+    /// Milliseconds since UNIX epoch.
+    pub finished_at: Option<u64>,
+    pub abandoned: bool,
+}
+
+/// Serializable form of the bookkeeping of a session, part of a `DatabaseSnapshot`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseSessionTrace {
+    pub session_id: String,
+    pub sender_id: u8,
+    pub direction: SessionDirection,
+    pub total_fragments: u64,
+    pub transferred_fragments: Vec<u64>,
+    pub acked_fragments: u64,
+    pub nacks: u64,
+    pub retransmissions: u64,
+    pub routes: Vec<Vec<NodeId>>,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub abandoned: bool,
+}
+
+/// Bookkeeping from which a `SessionStatus` is built.
+pub(super) struct SessionTrace {
+    direction: SessionDirection,
+    total_fragments: u64,
+    transferred_fragments: HashSet<u64>,
+    acked_fragments: u64,
+    nacks: u64,
+    retransmissions: u64,
+    routes: Vec<Vec<NodeId>>,
+    started_at: u64,
+    finished_at: Option<u64>,
+    abandoned: bool,
+}
+
+impl SessionTrace {
+    fn new(direction: SessionDirection, total_fragments: u64, started_at: u64) -> Self {
+        SessionTrace {
+            direction,
+            total_fragments,
+            transferred_fragments: HashSet::new(),
+            acked_fragments: 0,
+            nacks: 0,
+            retransmissions: 0,
+            routes: vec![],
+            started_at,
+            finished_at: None,
+            abandoned: false,
+        }
+    }
+}
+
+impl Database {
+    /// Records that a fragment was sent to or received from the network.
+    ///
+    /// Sending a fragment that has already been sent counts as a retransmission.
+    /// Returns whether the transfer was a retransmission.
+    pub fn record_fragment_transfer(&mut self, packet: &Packet) -> bool {
+        let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+            return false;
+        };
+        let Some(sender_id) = packet.routing_header.hops.first() else {
+            return false;
+        };
+        let direction = if *sender_id == self.node_id {
+            SessionDirection::Outbound
+        } else {
+            SessionDirection::Inbound
+        };
+        let session = PacketID2(SessionID(packet.session_id), SenderID(*sender_id));
+        let now = self.clock.now_millis();
+        let trace = self
+            .session_traces
+            .entry(session)
+            .or_insert_with(|| SessionTrace::new(direction, fragment.total_n_fragments, now));
+
+        let first_transfer = trace.transferred_fragments.insert(fragment.fragment_index);
+        let retransmission = !first_transfer && direction == SessionDirection::Outbound;
+        if retransmission {
+            trace.retransmissions += 1;
+        }
+        if !trace.routes.contains(&packet.routing_header.hops) {
+            trace.routes.push(packet.routing_header.hops.clone());
+        }
+        retransmission
+    }
+
+    pub(super) fn record_fragment_acked(&mut self, session: PacketID2) {
+        if let Some(trace) = self.session_traces.get_mut(&session) {
+            trace.acked_fragments += 1;
+        }
+    }
+
+    pub fn record_fragment_nacked(&mut self, session_id: u64, sender_id: u8) {
+        let session = PacketID2(SessionID(session_id), SenderID(sender_id));
+        if let Some(trace) = self.session_traces.get_mut(&session) {
+            trace.nacks += 1;
+        }
+    }
+
+    /// Records that a sent message was fully ACKed or a received message was reassembled.
+    ///
+    /// Returns the duration of the session in milliseconds if it had not
+    /// been finished before.
+    pub fn record_session_finished(&mut self, session_id: u64, sender_id: u8) -> Option<u64> {
+        let session = PacketID2(SessionID(session_id), SenderID(sender_id));
+        let now = self.clock.now_millis();
+        let trace = self.session_traces.get_mut(&session)?;
+        if trace.finished_at.is_some() {
+            return None;
+        }
+        trace.finished_at = Some(now);
+        let duration = now.saturating_sub(trace.started_at);
+        self.prune_finished_session_traces();
+        Some(duration)
+    }
+
+    pub(super) fn record_session_abandoned(&mut self, session: PacketID2) {
+        if let Some(trace) = self.session_traces.get_mut(&session) {
+            trace.abandoned = true;
+        }
+        self.prune_abandoned_session_traces();
+    }
+
+    fn prune_abandoned_session_traces(&mut self) {
+        self.prune_session_traces(MAX_ABANDONED_SESSION_TRACES, |trace| {
+            trace.abandoned.then_some(trace.started_at)
+        });
+    }
+
+    fn prune_finished_session_traces(&mut self) {
+        self.prune_session_traces(MAX_FINISHED_SESSION_TRACES, |trace| trace.finished_at);
+    }
+
+    /// Keeps at most `max` of the traces for which `closed_at` returns a
+    /// time, removing the ones closed earliest.
+    fn prune_session_traces(
+        &mut self,
+        max: usize,
+        closed_at: impl Fn(&SessionTrace) -> Option<u64>,
+    ) {
+        let mut closed: Vec<(u64, PacketID2)> = self
+            .session_traces
+            .iter()
+            .filter_map(|(session, trace)| closed_at(trace).map(|time| (time, *session)))
+            .collect();
+        if closed.len() <= max {
+            return;
+        }
+        closed.sort_unstable();
+        for (_, session) in &closed[..closed.len() - max] {
+            self.session_traces.remove(session);
+        }
+    }
+
+    pub fn get_session_status(&self, session_id: u64, sender_id: u8) -> Option<SessionStatus> {
+        let session = PacketID2(SessionID(session_id), SenderID(sender_id));
+        self.session_traces
+            .get(&session)
+            .map(|trace| Self::build_session_status(session, trace))
+    }
+
+    /// Returns the status of every tracked session ordered by start time.
+    pub fn get_session_statuses(&self) -> Vec<SessionStatus> {
+        let mut statuses: Vec<SessionStatus> = self
+            .session_traces
+            .iter()
+            .map(|(session, trace)| Self::build_session_status(*session, trace))
+            .collect();
+        statuses.sort_by_key(|status| (status.started_at, status.session_id, status.sender_id));
+        statuses
+    }
+
+    pub(super) fn export_session_traces(&self) -> Vec<DatabaseSessionTrace> {
+        let mut records: Vec<DatabaseSessionTrace> = self
+            .session_traces
+            .iter()
+            .map(|(session, trace)| {
+                let mut transferred_fragments: Vec<u64> =
+                    trace.transferred_fragments.iter().copied().collect();
+                transferred_fragments.sort_unstable();
+                DatabaseSessionTrace {
+                    session_id: session.0.to_string(),
+                    sender_id: session.1.0,
+                    direction: trace.direction,
+                    total_fragments: trace.total_fragments,
+                    transferred_fragments,
+                    acked_fragments: trace.acked_fragments,
+                    nacks: trace.nacks,
+                    retransmissions: trace.retransmissions,
+                    routes: trace.routes.clone(),
+                    started_at: trace.started_at,
+                    finished_at: trace.finished_at,
+                    abandoned: trace.abandoned,
+                }
+            })
+            .collect();
+        records.sort_by_key(|record| (record.started_at, record.sender_id));
+        records
+    }
+
+    pub(super) fn import_session_traces(
+        &mut self,
+        records: Vec<DatabaseSessionTrace>,
+    ) -> Result<()> {
+        for record in records {
+            let session = PacketID2(
+                SessionID(record.session_id.parse()?),
+                SenderID(record.sender_id),
+            );
+            self.session_traces.insert(
+                session,
+                SessionTrace {
+                    direction: record.direction,
+                    total_fragments: record.total_fragments,
+                    transferred_fragments: record.transferred_fragments.into_iter().collect(),
+                    acked_fragments: record.acked_fragments,
+                    nacks: record.nacks,
+                    retransmissions: record.retransmissions,
+                    routes: record.routes,
+                    started_at: record.started_at,
+                    finished_at: record.finished_at,
+                    abandoned: record.abandoned,
+                },
+            );
+        }
+        self.prune_abandoned_session_traces();
+        self.prune_finished_session_traces();
+        Ok(())
+    }
+
+    fn build_session_status(session: PacketID2, trace: &SessionTrace) -> SessionStatus {
+        SessionStatus {
+            session_id: session.0.0,
+            sender_id: session.1.0,
+            direction: trace.direction,
+            total_fragments: trace.total_fragments,
+            fragments_transferred: trace.transferred_fragments.len() as u64,
+            fragments_acked: trace.acked_fragments,
+            fragments_nacked: trace.nacks,
+            retransmissions: trace.retransmissions,
+            routes: trace.routes.clone(),
+            started_at: trace.started_at,
+            finished_at: trace.finished_at,
+            abandoned: trace.abandoned,
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::{Context, Result, anyhow};
+use messages::MessageType;
+use serde::{Deserialize, Serialize};
+use wg_2024::packet::Fragment;
+
+use super::message::{DatabaseMessage, MessageID, SenderID, SessionID};
+use super::packet::{DatabasePacket, DatabaseSession, FragmentID, PacketID, PacketID2};
+use super::retention::RetentionPolicy;
+use super::session::DatabaseSessionTrace;
+use super::{Database, Packet, PacketStore, PacketType, SourceRoutingHeader};
+
+/// Complete state of a `Database` in a serializable form.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseSnapshot {
+    pub node_id: u8,
+    pub messages: Vec<DatabaseMessage>,
+    pub sessions: Vec<DatabaseSession>,
+    pub packets: Vec<DatabasePacket>,
+    #[serde(default)]
+    pub session_traces: Vec<DatabaseSessionTrace>,
+}
+
+impl DatabaseSnapshot {
+    /// # Errors
+    /// Returns an error if the JSON does not describe a snapshot.
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).with_context(|| "Failed to parse database snapshot!")
+    }
+
+    /// # Errors
+    /// Returns an error if the snapshot cannot be serialized.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).with_context(|| "Failed to serialize database snapshot!")
+    }
+}
+
+impl DatabasePacket {
+    fn new(
+        packet_id: PacketID,
+        packet: &Packet,
+        sent_to_sc: bool,
+        ack_received: bool,
+    ) -> Option<Self> {
+        let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+            return None;
+        };
+        Some(DatabasePacket {
+            packet_id: packet_id.to_string(),
+            routing_header_hop_index: packet.routing_header.hop_index,
+            routing_header_hops: packet.routing_header.hops.clone(),
+            session_id: packet.session_id.to_string(),
+            sender_id: packet_id.1.0,
+            fragment_index: fragment.fragment_index.to_string(),
+            total_n_fragments: fragment.total_n_fragments.to_string(),
+            length: fragment.length,
+            data: fragment.data.to_vec(),
+            sent_to_sc,
+            ack_received,
+        })
+    }
+
+    fn to_packet(&self) -> Result<(PacketID, Packet)> {
+        let session_id: u64 = self.session_id.parse()?;
+        let fragment_index: u64 = self.fragment_index.parse()?;
+        let fragment = Fragment {
+            fragment_index,
+            total_n_fragments: self.total_n_fragments.parse()?,
+            length: self.length,
+            data: self
+                .data
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow!("Fragment {} has invalid data length!", self.packet_id))?,
+        };
+        let packet = Packet {
+            routing_header: SourceRoutingHeader::new(
+                self.routing_header_hops.clone(),
+                self.routing_header_hop_index,
+            ),
+            session_id,
+            pack_type: PacketType::MsgFragment(fragment),
+        };
+        let packet_id = PacketID(
+            SessionID(session_id),
+            SenderID(self.sender_id),
+            FragmentID(fragment_index),
+        );
+        Ok((packet_id, packet))
+    }
+}
+
+impl Database {
+    /// Returns the complete state of the database.
+    pub fn export_snapshot(&self) -> DatabaseSnapshot {
+        let mut messages = vec![];
+        for message_id in &self.message_order {
+            let message = self.messages.get(message_id);
+            let meta = self.message_meta.get(message_id);
+            if let (Some(message), Some(meta)) = (message, meta) {
+                messages.push(DatabaseMessage {
+                    message_id: message_id.to_string(),
+                    session_id: message_id.0.to_string(),
+                    sender_id: message_id.1.0,
+                    message: message.clone(),
+                    meta: *meta,
+                    read: self.is_message_read(*message_id),
+                    sent_to_sc: self.is_message_sent_to_sc(*message_id),
+                });
+            }
+        }
+
+        let mut sessions = vec![];
+        let mut packets = vec![];
+        for (session, packet_store) in &self.packets {
+            sessions.push(DatabaseSession {
+                session_id: session.0.to_string(),
+                sender_id: session.1.0,
+                total_n_fragments: packet_store.total_amount_of_frags,
+                received_n_fragments: packet_store.received_amount_of_frags,
+                all_fragments_received: packet_store.all_fragments_received,
+                fragments_released: packet_store.fragments_released,
+            });
+            for (fragment_index, packet) in &packet_store.packets {
+                let packet_id = PacketID(session.0, session.1, FragmentID(*fragment_index));
+                packets.extend(DatabasePacket::new(
+                    packet_id,
+                    packet,
+                    self.is_packet_sent_to_sc(packet_id),
+                    self.is_packet_ack_received(packet_id),
+                ));
+            }
+        }
+
+        DatabaseSnapshot {
+            node_id: self.node_id,
+            messages,
+            sessions,
+            packets,
+            session_traces: self.export_session_traces(),
+        }
+    }
+
+    /// Rebuilds a database from a snapshot.
+    ///
+    /// # Errors
+    /// Returns an error if a record of the snapshot is malformed.
+    pub fn from_snapshot(snapshot: DatabaseSnapshot, retention: RetentionPolicy) -> Result<Self> {
+        let mut database = Database::with_retention_policy(snapshot.node_id, retention);
+
+        for record in snapshot.messages {
+            let message_id = MessageID(
+                SessionID(record.session_id.parse()?),
+                SenderID(record.sender_id),
+            );
+            let peer = record.meta.peer;
+            if record.read {
+                database.messages_read.insert(message_id);
+            }
+            if record.sent_to_sc {
+                database.messages_sent_to_sc.insert(message_id);
+            }
+            if record.meta.replied_by.is_none()
+                && matches!(record.message.content, MessageType::Request(_))
+            {
+                database
+                    .pending_requests
+                    .entry(peer)
+                    .or_default()
+                    .push_back(message_id);
+            }
+            database.messages.insert(message_id, record.message);
+            database.message_meta.insert(message_id, record.meta);
+            database.message_order.push(message_id);
+            database
+                .conversations
+                .entry(peer)
+                .or_default()
+                .push(message_id);
+        }
+
+        for record in snapshot.sessions {
+            let session = PacketID2(
+                SessionID(record.session_id.parse()?),
+                SenderID(record.sender_id),
+            );
+            let mut packet_store =
+                PacketStore::new(record.total_n_fragments, database.clock.now_millis());
+            packet_store.received_amount_of_frags = record.received_n_fragments;
+            packet_store.all_fragments_received = record.all_fragments_received;
+            packet_store.fragments_released = record.fragments_released;
+            database.packets.insert(session, packet_store);
+        }
+
+        for record in snapshot.packets {
+            let (packet_id, packet) = record.to_packet()?;
+            let packet_store = database
+                .packets
+                .get_mut(&PacketID2(packet_id.0, packet_id.1))
+                .with_context(|| format!("Snapshot has packet {packet_id} without a session!"))?;
+            packet_store.packets.insert(packet_id.2.0, packet);
+            if record.sent_to_sc {
+                database.packets_sent_to_sc.insert(packet_id);
+            }
+            if record.ack_received {
+                database.packets_received_ack.insert(packet_id);
+            }
+        }
+
+        database.import_session_traces(snapshot.session_traces)?;
+
+        Ok(database)
+    }
+}
@@ -1,4 +1,7 @@
 pub mod backend;
+mod clock;
 mod database;
 mod network;
 mod packet;
+#[cfg(feature = "testing")]
+pub mod testing;
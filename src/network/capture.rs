@@ -0,0 +1,205 @@
+//! Recording of all packets a client sends and receives, and replay of
+//! recorded traffic.
+//!
+//! A traffic log is a file of JSON lines, each holding one `TrafficRecord`.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use wg_2024::packet::Packet;
+
+use crate::clock::Clock;
+
+/// Whether a packet was received or sent by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrafficDirection {
+    Inbound,
+    Outbound,
+}
+
+/// A single packet of a traffic log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficRecord {
+    /// Time the packet was handled in milliseconds since UNIX epoch.
+    pub timestamp: u64,
+    pub direction: TrafficDirection,
+    pub packet: Packet,
+}
+
+impl TrafficRecord {
+    /// Reads all records of a traffic log.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or a line is not a record.
+    pub fn read_log(path: &Path) -> Result<Vec<TrafficRecord>> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open traffic log {}!", path.display()))?;
+        let mut records = vec![];
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line).with_context(|| {
+                format!(
+                    "Line {} of {} is not a traffic record!",
+                    index + 1,
+                    path.display()
+                )
+            })?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+/// Writes every packet handed to it to a traffic log.
+#[derive(Debug)]
+pub struct TrafficRecorder {
+    writer: LineWriter<File>,
+    clock: Arc<dyn Clock>,
+}
+
+impl TrafficRecorder {
+    /// Creates a recorder writing to `path`, replacing an existing file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created.
+    pub fn create(path: &Path, clock: Arc<dyn Clock>) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create traffic log {}!", path.display()))?;
+        Ok(TrafficRecorder {
+            writer: LineWriter::new(file),
+            clock,
+        })
+    }
+
+    /// Appends `packet` to the log.
+    ///
+    /// # Errors
+    /// Returns an error if the record cannot be written.
+    pub fn record(&mut self, direction: TrafficDirection, packet: &Packet) -> Result<()> {
+        let record = TrafficRecord {
+            timestamp: self.clock.now_millis(),
+            direction,
+            packet: packet.clone(),
+        };
+        let line = serde_json::to_string(&record)?;
+        writeln!(self.writer, "{line}").with_context(|| "Failed to write traffic record!")?;
+        Ok(())
+    }
+}
+
+/// Feeds the inbound packets of `records` in their recorded order to the
+/// inbound packet channel of a client, returning the amount of packets sent.
+///
+/// Outbound records are skipped, as they are what the client is expected to
+/// produce in response.
+///
+/// # Errors
+/// Returns an error if the channel is disconnected.
+pub fn replay_inbound(
+    records: &[TrafficRecord],
+    inbound_packets: &Sender<Packet>,
+) -> Result<usize> {
+    let mut replayed = 0;
+    for record in records
+        .iter()
+        .filter(|record| record.direction == TrafficDirection::Inbound)
+    {
+        inbound_packets
+            .send(record.packet.clone())
+            .with_context(|| "Failed to replay packet, client is not listening!")?;
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
+    use std::time::Duration;
+
+    use crossbeam_channel::unbounded;
+    use pretty_assertions::assert_eq;
+    use wg_2024::network::SourceRoutingHeader;
+    use wg_2024::packet::{Ack, PacketType};
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn ack(session_id: u64, fragment_index: u64) -> Packet {
+        Packet {
+            routing_header: SourceRoutingHeader {
+                hop_index: 1,
+                hops: vec![4, 2, 1],
+            },
+            session_id,
+            pack_type: PacketType::Ack(Ack { fragment_index }),
+        }
+    }
+
+    fn log_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("traffic-{}-{name}.jsonl", std::process::id()))
+    }
+
+    #[test]
+    fn test_recorded_traffic_is_read_back() {
+        let path = log_path("read-back");
+        let clock = ManualClock::new(1_000);
+        let mut recorder = TrafficRecorder::create(&path, Arc::new(clock.clone())).unwrap();
+
+        recorder
+            .record(TrafficDirection::Inbound, &ack(1, 0))
+            .unwrap();
+        clock.advance(Duration::from_millis(5));
+        recorder
+            .record(TrafficDirection::Outbound, &ack(2, 3))
+            .unwrap();
+        drop(recorder);
+
+        let records = TrafficRecord::read_log(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp, 1_000);
+        assert_eq!(records[0].direction, TrafficDirection::Inbound);
+        assert_eq!(records[0].packet.session_id, 1);
+        assert_eq!(records[1].timestamp, 1_005);
+        assert_eq!(records[1].direction, TrafficDirection::Outbound);
+        assert_eq!(
+            records[1].packet.pack_type,
+            PacketType::Ack(Ack { fragment_index: 3 })
+        );
+    }
+
+    #[test]
+    fn test_replay_sends_only_inbound_packets_in_order() {
+        let records: Vec<TrafficRecord> = [
+            (TrafficDirection::Inbound, 1),
+            (TrafficDirection::Outbound, 2),
+            (TrafficDirection::Inbound, 3),
+        ]
+        .into_iter()
+        .map(|(direction, session_id)| TrafficRecord {
+            timestamp: 0,
+            direction,
+            packet: ack(session_id, 0),
+        })
+        .collect();
+        let (sender, receiver) = unbounded();
+
+        assert_eq!(replay_inbound(&records, &sender).unwrap(), 2);
+        let replayed: Vec<u64> = receiver
+            .try_iter()
+            .map(|packet| packet.session_id)
+            .collect();
+        assert_eq!(replayed, vec![1, 3]);
+    }
+}
@@ -1,11 +1,14 @@
 #![allow(dead_code)]
 
-use anyhow::{Context, Result};
-use crossbeam_channel::Sender;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use anyhow::{Context, Result, anyhow};
 use log::info;
 use messages::node_event::{EventNetworkGraph, EventNetworkNode, NodeEvent};
 use petgraph::{algo::simple_paths, visit::Visitable};
-use rand::{Rng, rng};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use wg_2024::{network::NodeId, packet::NodeType};
 
@@ -27,9 +30,139 @@ enum NodeTypeWrapper {
 /// A directed network graph that stores vertices (`Vertice`) and their connections.
 ///
 /// Internally uses `petgraph::graphmap::DiGraphMap` and ensures edges are bidirectional.
+///
+/// Random route selection draws from the graph's own RNG, so a graph created
+/// with `NetGraph::with_seed` picks the same routes on every run.
 pub struct NetGraph {
     graph: petgraph::graphmap::DiGraphMap<Vertice, ()>,
     node_id: u8,
+    rng: StdRng,
+    /// Amount of packets each drone reported as dropped.
+    dropped_packets: HashMap<NodeId, u64>,
+}
+
+/// A node of a `KnownTopology`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopologyNode {
+    pub node_id: NodeId,
+    pub node_type: NodeType,
+    /// Packets the node reported as dropped to this client.
+    pub dropped_packets: u64,
+}
+
+/// The network as a client believes it to be, in a serializable form.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownTopology {
+    /// The client the topology is known to.
+    pub node_id: NodeId,
+    /// Nodes ordered by ID.
+    pub nodes: Vec<TopologyNode>,
+    /// Undirected edges with the lower ID first, ordered.
+    pub edges: Vec<(NodeId, NodeId)>,
+}
+
+/// Network description in the TOML format the simulation controller loads.
+#[derive(Debug, Deserialize)]
+struct NetworkConfig {
+    #[serde(default)]
+    drone: Vec<DroneConfig>,
+    #[serde(default)]
+    client: Vec<EdgeNodeConfig>,
+    #[serde(default)]
+    server: Vec<EdgeNodeConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DroneConfig {
+    id: NodeId,
+    connected_node_ids: Vec<NodeId>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EdgeNodeConfig {
+    id: NodeId,
+    connected_drone_ids: Vec<NodeId>,
+}
+
+impl KnownTopology {
+    /// Parses a topology exported with `KnownTopology::to_json`.
+    ///
+    /// # Errors
+    /// Returns an error if the JSON does not describe a topology.
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).with_context(|| "Failed to parse topology!")
+    }
+
+    /// Builds the topology of client `node_id` from the TOML network
+    /// configuration of the simulation controller.
+    ///
+    /// # Errors
+    /// Returns an error if the TOML does not describe a network.
+    pub fn from_network_config(toml: &str, node_id: NodeId) -> Result<Self> {
+        let config: NetworkConfig =
+            toml::from_str(toml).with_context(|| "Failed to parse network configuration!")?;
+
+        let mut nodes = vec![];
+        let mut edges = BTreeSet::new();
+        let mut add_node = |id: NodeId, node_type: NodeType, neighbors: &[NodeId]| {
+            nodes.push(TopologyNode {
+                node_id: id,
+                node_type,
+                dropped_packets: 0,
+            });
+            for &neighbor in neighbors {
+                edges.insert((id.min(neighbor), id.max(neighbor)));
+            }
+        };
+        for drone in &config.drone {
+            add_node(drone.id, NodeType::Drone, &drone.connected_node_ids);
+        }
+        for client in &config.client {
+            add_node(client.id, NodeType::Client, &client.connected_drone_ids);
+        }
+        for server in &config.server {
+            add_node(server.id, NodeType::Server, &server.connected_drone_ids);
+        }
+        nodes.sort_by_key(|node| node.node_id);
+
+        Ok(KnownTopology {
+            node_id,
+            nodes,
+            edges: edges.into_iter().collect(),
+        })
+    }
+
+    /// # Errors
+    /// Returns an error if the topology cannot be serialized.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).with_context(|| "Failed to serialize topology!")
+    }
+
+    /// Renders the topology as a Graphviz DOT graph.
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("graph client_{} {{\n", self.node_id);
+        for node in &self.nodes {
+            let shape = match node.node_type {
+                NodeType::Client => "box",
+                NodeType::Drone => "ellipse",
+                NodeType::Server => "diamond",
+            };
+            let mut label = format!("{} {:?}", node.node_id, node.node_type);
+            if node.dropped_packets > 0 {
+                let _ = write!(label, "\\ndropped {}", node.dropped_packets);
+            }
+            let _ = writeln!(
+                dot,
+                "    {} [label=\"{label}\", shape={shape}];",
+                node.node_id
+            );
+        }
+        for (a, b) in &self.edges {
+            let _ = writeln!(dot, "    {a} -- {b};");
+        }
+        dot.push_str("}\n");
+        dot
+    }
 }
 
 impl Vertice {
@@ -59,8 +192,18 @@ impl Vertice {
 impl NetGraph {
     /// Creates a new empty network graph associated with a given `node_id`.
     pub fn new(node_id: u8) -> Self {
+        Self::with_seed(node_id, rand::random())
+    }
+
+    /// Creates a new empty network graph whose random choices are determined by `seed`.
+    pub fn with_seed(node_id: u8, seed: u64) -> Self {
         let graph = petgraph::graphmap::DiGraphMap::new();
-        NetGraph { graph, node_id }
+        NetGraph {
+            graph,
+            node_id,
+            rng: StdRng::seed_from_u64(seed),
+            dropped_packets: HashMap::new(),
+        }
     }
 
     /// Adds a vertex to the graph if it does not already exist.
@@ -88,37 +231,51 @@ impl NetGraph {
         }
     }
 
-    /// Adds a new route to the graph and notifies the SC (service controller) of known topology.
+    /// Adds the path trace of a flood response to the graph.
     ///
-    /// - Stops early if a `Client` or `Server` node appears mid-path.
+    /// - The trace must start at this client.
+    /// - Only drones may appear between the first and the last node. The last
+    ///   node, e.g. the server that answered the flood, is recorded whatever its type.
     /// - Adds vertices and bidirectional edges for each step in the route.
-    /// - Sends a `KnownNetworkGraph` event over the provided channel.
-    pub fn add_route(
-        &mut self,
-        route: &[(NodeId, NodeType)],
-        outbound_sc_event_channel: &Sender<NodeEvent>,
-    ) -> Result<()> {
+    ///
+    /// Returns an error, leaving the graph unchanged, if the trace is malformed.
+    pub fn add_route(&mut self, route: &[(NodeId, NodeType)]) -> Result<()> {
         info!("Saving a new path trace {route:?}");
-        for i in 0..route.len() - 1 {
-            let before = &route[i];
-            let after = &route[i + 1];
-
-            if i != route.len() - 1 {
-                match after.1 {
-                    NodeType::Client | NodeType::Server => return Ok(()),
-                    NodeType::Drone => {}
-                }
-            }
-            self.save_vertices_to_graph(Vertice::new(*before));
-            self.save_vertices_to_graph(Vertice::new(*after));
+        Self::validate_path_trace(self.node_id, route)?;
 
-            self.insert_edge_between_nodes(*before, *after);
+        for node in route {
+            self.save_vertices_to_graph(Vertice::new(*node));
+        }
+        for step in route.windows(2) {
+            self.insert_edge_between_nodes(step[0], step[1]);
         }
-        self.notify_sc_of_known_topology(outbound_sc_event_channel)?;
 
         Ok(())
     }
 
+    /// Checks that `route` starts at client `node_id` and passes only drones
+    /// on its way to the last node.
+    fn validate_path_trace(node_id: NodeId, route: &[(NodeId, NodeType)]) -> Result<()> {
+        let Some(first) = route.first() else {
+            return Err(anyhow!("Received an empty path trace!"));
+        };
+        if *first != (node_id, NodeType::Client) {
+            return Err(anyhow!(
+                "Path trace {route:?} does not start at client {node_id}!"
+            ));
+        }
+        let middle = route.get(1..route.len() - 1).unwrap_or_default();
+        if let Some(node) = middle
+            .iter()
+            .find(|(_, node_type)| *node_type != NodeType::Drone)
+        {
+            return Err(anyhow!(
+                "Path trace {route:?} passes through {node:?}, which is not a drone!"
+            ));
+        }
+        Ok(())
+    }
+
     /// Returns a list of all non-drone nodes in the graph, or `None` if there are none.
     pub fn get_edge_nodes(&self) -> Option<Vec<(NodeId, NodeType)>> {
         let edge_vertices: Vec<Vertice> = self
@@ -133,11 +290,9 @@ impl NetGraph {
         if nodes.is_empty() { None } else { Some(nodes) }
     }
 
-    /// Sends a `KnownNetworkGraph` event containing the current topology to the SC.
-    fn notify_sc_of_known_topology(
-        &self,
-        outbound_sc_event_channel: &Sender<NodeEvent>,
-    ) -> Result<()> {
+    /// Returns a `KnownNetworkGraph` event describing the current topology
+    /// for the SC (service controller).
+    pub fn known_network_graph_event(&self) -> NodeEvent {
         let mut nodes = EventNetworkGraph { nodes: vec![] };
 
         for node in self.graph.nodes() {
@@ -151,20 +306,26 @@ impl NetGraph {
             nodes.nodes.push(event);
         }
 
-        let event = NodeEvent::KnownNetworkGraph {
+        NodeEvent::KnownNetworkGraph {
             source: self.node_id,
             graph: nodes,
-        };
-        outbound_sc_event_channel.send(event)?;
-        Ok(())
+        }
     }
 
     /// Computes all simple routes between two vertices in the graph.
+    /// Only drones may appear between the two vertices.
     ///
     /// Returns each route as a list of `NodeId`s.
     fn compute_routes(&self, from: Vertice, to: Vertice) -> Vec<Vec<u8>> {
         let routes: Vec<Vec<Vertice>> =
-            simple_paths::all_simple_paths(&self.graph, from, to, 0, None).collect();
+            simple_paths::all_simple_paths(&self.graph, from, to, 0, None)
+                .filter(|route: &Vec<Vertice>| {
+                    route.len() < 2
+                        || route[1..route.len() - 1]
+                            .iter()
+                            .all(|vertice| vertice.node_type == NodeTypeWrapper::Drone)
+                })
+                .collect();
         routes
             .iter()
             .map(|vert_vec| vert_vec.iter().map(|vertice| vertice.node_id).collect())
@@ -172,12 +333,12 @@ impl NetGraph {
     }
 
     /// Returns a random route between two vertices, or `None` if no route exists.
-    pub fn get_random_route(&self, from: Vertice, to: Vertice) -> Option<Vec<u8>> {
+    pub fn get_random_route(&mut self, from: Vertice, to: Vertice) -> Option<Vec<u8>> {
         let routes = self.compute_routes(from, to);
         if routes.is_empty() {
             return None;
         }
-        let random_index = rng().random_range(0..routes.len());
+        let random_index = self.rng.random_range(0..routes.len());
         routes.get(random_index).cloned()
     }
 
@@ -197,6 +358,103 @@ impl NetGraph {
             .get_node_type())
     }
 
+    /// Records that `node_id` dropped a packet sent by this client.
+    pub fn record_dropped_packet(&mut self, node_id: NodeId) {
+        *self.dropped_packets.entry(node_id).or_default() += 1;
+    }
+
+    /// Adds the nodes and edges of `topology` to the graph, so that routes
+    /// are known without flooding. Drop counts of the topology replace the
+    /// ones recorded so far.
+    ///
+    /// Returns an error, leaving the graph unchanged, if this client is not
+    /// part of the topology or an edge connects an unknown node.
+    pub fn load_topology(&mut self, topology: &KnownTopology) -> Result<()> {
+        let node_types: HashMap<NodeId, NodeType> = topology
+            .nodes
+            .iter()
+            .map(|node| (node.node_id, node.node_type))
+            .collect();
+        match node_types.get(&self.node_id) {
+            Some(NodeType::Client) => {}
+            _ => {
+                return Err(anyhow!(
+                    "Topology does not contain client {} and cannot be loaded!",
+                    self.node_id
+                ));
+            }
+        }
+        let mut edges = vec![];
+        for &(a, b) in &topology.edges {
+            let (Some(a_type), Some(b_type)) = (node_types.get(&a), node_types.get(&b)) else {
+                return Err(anyhow!(
+                    "Topology has an edge {a} - {b} to an unknown node!"
+                ));
+            };
+            edges.push(((a, *a_type), (b, *b_type)));
+        }
+
+        info!(
+            "Loading a topology of {} nodes and {} edges",
+            topology.nodes.len(),
+            topology.edges.len()
+        );
+        for node in &topology.nodes {
+            self.save_vertices_to_graph(Vertice::new((node.node_id, node.node_type)));
+            if node.dropped_packets > 0 {
+                self.dropped_packets
+                    .insert(node.node_id, node.dropped_packets);
+            }
+        }
+        for (a, b) in edges {
+            self.insert_edge_between_nodes(a, b);
+        }
+        Ok(())
+    }
+
+    /// Returns the known nodes and edges together with the drop counts of the nodes.
+    pub fn to_known_topology(&self) -> KnownTopology {
+        let mut nodes: Vec<TopologyNode> = self
+            .graph
+            .nodes()
+            .map(|vertice| TopologyNode {
+                node_id: vertice.node_id,
+                node_type: vertice.get_node_type(),
+                dropped_packets: self
+                    .dropped_packets
+                    .get(&vertice.node_id)
+                    .copied()
+                    .unwrap_or_default(),
+            })
+            .collect();
+        nodes.sort_by_key(|node| node.node_id);
+        let mut edges: Vec<(NodeId, NodeId)> = self
+            .graph
+            .all_edges()
+            .filter(|(from, to, _)| from.node_id < to.node_id)
+            .map(|(from, to, _)| (from.node_id, to.node_id))
+            .collect();
+        edges.sort_unstable();
+        KnownTopology {
+            node_id: self.node_id,
+            nodes,
+            edges,
+        }
+    }
+
+    /// Exports the known topology as JSON.
+    ///
+    /// # Errors
+    /// Returns an error if the topology cannot be serialized.
+    pub fn to_json(&self) -> Result<String> {
+        self.to_known_topology().to_json()
+    }
+
+    /// Exports the known topology as a Graphviz DOT graph.
+    pub fn to_dot(&self) -> String {
+        self.to_known_topology().to_dot()
+    }
+
     /// Clears all vertices and edges from the graph.
     pub fn reset(&mut self) {
         self.graph.clear();
@@ -206,7 +464,6 @@ impl NetGraph {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crossbeam_channel::unbounded;
     use wg_2024::packet::NodeType;
 
     fn v(id: u8, t: NodeType) -> Vertice {
@@ -254,16 +511,76 @@ mod tests {
     }
 
     #[test]
-    #[allow(clippy::unwrap_used)]
-    fn test_add_route_stops_on_client_or_server() {
-        let mut graph = NetGraph::new(0);
-        let (tx, _rx) = unbounded();
+    #[allow(clippy::unwrap_used, clippy::panic)]
+    fn test_add_route_records_terminal_server() {
+        let mut graph = NetGraph::new(1);
 
-        // Middle node is a Client — should early return without inserting edges
         graph
-            .add_route(&[(1, NodeType::Drone), (2, NodeType::Client)], &tx)
+            .add_route(&[
+                (1, NodeType::Client),
+                (2, NodeType::Drone),
+                (3, NodeType::Drone),
+                (4, NodeType::Server),
+            ])
             .unwrap();
-        assert!(graph.graph.nodes().count() <= 1);
+        assert_eq!(graph.get_node_type(4).unwrap(), NodeType::Server);
+        assert!(
+            graph
+                .graph
+                .contains_edge(v(3, NodeType::Drone), v(4, NodeType::Server))
+        );
+        assert_eq!(
+            graph.get_random_route(v(1, NodeType::Client), v(4, NodeType::Server)),
+            Some(vec![1, 2, 3, 4])
+        );
+        let NodeEvent::KnownNetworkGraph {
+            source,
+            graph: event_graph,
+        } = graph.known_network_graph_event()
+        else {
+            panic!("Expected a KnownNetworkGraph event");
+        };
+        assert_eq!(source, 1);
+        assert_eq!(event_graph.nodes.len(), 4);
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn test_add_route_single_node_trace() {
+        let mut graph = NetGraph::new(1);
+
+        graph.add_route(&[(1, NodeType::Client)]).unwrap();
+        assert_eq!(graph.graph.nodes().count(), 1);
+        assert_eq!(graph.graph.edge_count(), 0);
+    }
+
+    #[test]
+    fn test_add_route_rejects_malformed_traces() {
+        let mut graph = NetGraph::new(1);
+
+        let malformed: [&[(NodeId, NodeType)]; 5] = [
+            // Empty trace
+            &[],
+            // Does not start at this client
+            &[(2, NodeType::Drone), (4, NodeType::Server)],
+            &[(5, NodeType::Client), (2, NodeType::Drone)],
+            // Client or server mid-path
+            &[
+                (1, NodeType::Client),
+                (2, NodeType::Drone),
+                (5, NodeType::Client),
+                (3, NodeType::Drone),
+            ],
+            &[
+                (1, NodeType::Client),
+                (4, NodeType::Server),
+                (2, NodeType::Drone),
+            ],
+        ];
+        for route in malformed {
+            assert!(graph.add_route(route).is_err(), "{route:?}");
+        }
+        assert_eq!(graph.graph.nodes().count(), 0);
     }
 
     #[test]
@@ -292,6 +609,159 @@ mod tests {
         assert!(random_route.is_some());
     }
 
+    #[test]
+    fn test_seeded_random_routes_are_reproducible() {
+        let pick_routes = |seed| {
+            let mut graph = NetGraph::with_seed(0, seed);
+            for middle in [2, 3, 5] {
+                graph.insert_edge_between_nodes((1, NodeType::Client), (middle, NodeType::Drone));
+                graph.insert_edge_between_nodes((middle, NodeType::Drone), (4, NodeType::Server));
+            }
+            (0..20)
+                .map(|_| graph.get_random_route(v(1, NodeType::Client), v(4, NodeType::Server)))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(pick_routes(42), pick_routes(42));
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn test_known_topology_export() {
+        let mut graph = NetGraph::new(1);
+        graph.insert_edge_between_nodes((1, NodeType::Client), (2, NodeType::Drone));
+        graph.insert_edge_between_nodes((3, NodeType::Drone), (2, NodeType::Drone));
+        graph.insert_edge_between_nodes((3, NodeType::Drone), (4, NodeType::Server));
+        graph.record_dropped_packet(3);
+        graph.record_dropped_packet(3);
+
+        let topology = graph.to_known_topology();
+        assert_eq!(topology.edges, vec![(1, 2), (2, 3), (3, 4)]);
+        assert_eq!(
+            topology.nodes[2],
+            TopologyNode {
+                node_id: 3,
+                node_type: NodeType::Drone,
+                dropped_packets: 2,
+            }
+        );
+
+        let parsed: KnownTopology = serde_json::from_str(&graph.to_json().unwrap()).unwrap();
+        assert_eq!(parsed, topology);
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("graph client_1 {\n"));
+        assert!(dot.contains("    3 [label=\"3 Drone\\ndropped 2\", shape=ellipse];\n"));
+        assert!(dot.contains("    4 [label=\"4 Server\", shape=diamond];\n"));
+        assert!(dot.contains("    2 -- 3;\n"));
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn test_load_exported_topology() {
+        let mut exporter = NetGraph::new(1);
+        exporter.insert_edge_between_nodes((1, NodeType::Client), (2, NodeType::Drone));
+        exporter.insert_edge_between_nodes((2, NodeType::Drone), (4, NodeType::Server));
+        exporter.record_dropped_packet(2);
+        let json = exporter.to_json().unwrap();
+
+        let mut graph = NetGraph::new(1);
+        graph
+            .load_topology(&KnownTopology::from_json(&json).unwrap())
+            .unwrap();
+        assert_eq!(graph.to_known_topology(), exporter.to_known_topology());
+        assert_eq!(
+            graph.get_random_route(v(1, NodeType::Client), v(4, NodeType::Server)),
+            Some(vec![1, 2, 4])
+        );
+    }
+
+    #[test]
+    fn test_load_topology_rejects_invalid_topologies() {
+        let mut graph = NetGraph::new(1);
+        let node = |node_id, node_type| TopologyNode {
+            node_id,
+            node_type,
+            dropped_packets: 0,
+        };
+
+        let without_self = KnownTopology {
+            node_id: 5,
+            nodes: vec![node(5, NodeType::Client), node(2, NodeType::Drone)],
+            edges: vec![(2, 5)],
+        };
+        assert!(graph.load_topology(&without_self).is_err());
+
+        let unknown_node = KnownTopology {
+            node_id: 1,
+            nodes: vec![node(1, NodeType::Client), node(2, NodeType::Drone)],
+            edges: vec![(1, 2), (2, 3)],
+        };
+        assert!(graph.load_topology(&unknown_node).is_err());
+        assert_eq!(graph.graph.nodes().count(), 0);
+    }
+
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn test_topology_from_network_config() {
+        let config = r#"
+            [[drone]]
+            id = 2
+            connected_node_ids = [1, 3, 4]
+            pdr = 0.05
+
+            [[drone]]
+            id = 3
+            connected_node_ids = [2, 4]
+            pdr = 0.1
+
+            [[client]]
+            id = 1
+            connected_drone_ids = [2]
+
+            [[server]]
+            id = 4
+            connected_drone_ids = [2, 3]
+        "#;
+
+        let topology = KnownTopology::from_network_config(config, 1).unwrap();
+        assert_eq!(topology.node_id, 1);
+        assert_eq!(
+            topology
+                .nodes
+                .iter()
+                .map(|node| (node.node_id, node.node_type))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, NodeType::Client),
+                (2, NodeType::Drone),
+                (3, NodeType::Drone),
+                (4, NodeType::Server),
+            ]
+        );
+        assert_eq!(topology.edges, vec![(1, 2), (2, 3), (2, 4), (3, 4)]);
+    }
+
+    #[test]
+    fn test_routes_do_not_pass_through_edge_nodes() {
+        let mut graph = NetGraph::new(1);
+        graph.insert_edge_between_nodes((1, NodeType::Client), (2, NodeType::Drone));
+        graph.insert_edge_between_nodes((2, NodeType::Drone), (5, NodeType::Client));
+        graph.insert_edge_between_nodes((5, NodeType::Client), (3, NodeType::Drone));
+        graph.insert_edge_between_nodes((2, NodeType::Drone), (4, NodeType::Server));
+        graph.insert_edge_between_nodes((4, NodeType::Server), (3, NodeType::Drone));
+
+        assert!(
+            graph
+                .compute_routes(v(1, NodeType::Client), v(3, NodeType::Drone))
+                .is_empty()
+        );
+        assert_eq!(
+            graph.compute_routes(v(1, NodeType::Client), v(4, NodeType::Server)),
+            vec![vec![1, 2, 4]]
+        );
+    }
+
     #[test]
     #[allow(clippy::unwrap_used)]
     fn test_get_node_type_existing_and_missing() {
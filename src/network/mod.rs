@@ -1,2 +1,5 @@
-mod graph;
+pub mod capture;
+pub mod graph;
 pub(crate) mod router;
+mod spans;
+pub mod stats;
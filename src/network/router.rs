@@ -1,23 +1,49 @@
 #![allow(clippy::too_many_arguments)]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 // TODO remove
 use anyhow::{Context, Result, anyhow};
 use crossbeam_channel::{Receiver, Sender, select};
-use log::{error, info};
+use log::{error, info, warn};
 use messages::Message;
 use messages::node_event::NodeEvent;
 use wg_2024::network::{NodeId, SourceRoutingHeader};
-use wg_2024::packet::{FloodRequest, FloodResponse, NackType, Packet, PacketType};
+use wg_2024::packet::{FloodRequest, FloodResponse, Nack, NackType, Packet, PacketType};
 use wg_2024::{controller::DroneCommand, packet::NodeType};
 
+use super::capture::{TrafficDirection, TrafficRecorder};
 use super::graph::{NetGraph, Vertice};
-use crate::backend::{self, Command, ListOfDiscoveredEdgeNodes, UnreadMessagesFromServer};
-use crate::database::Database;
+use super::spans::TransmissionSpans;
+use super::stats::Stats;
+use crate::backend::{
+    self, ApiResponse, Command, ControllerCommand, InboxEntry, InboxPage,
+    ListOfDiscoveredEdgeNodes, NeighborPolicy, RetentionPolicy, ServiceConfig, ServiceEvent,
+    SignaturePolicy, Transcript, TranscriptEntry, UnreadCounts, UnreadMessagesFromServer,
+};
 use crate::database::message::{MessageID, SenderID, SessionID};
 use crate::database::packet::{FragmentID, PacketID};
+use crate::database::snapshot::DatabaseSnapshot;
+use crate::database::{AbandonedSession, Database};
 use crate::packet;
+use crate::packet::codec::FragmentCodec;
+use crate::packet::encryption::PeerKey;
+use crate::packet::envelope::{DecodeOptions, EncodeOptions, MessageEncoding};
+use crate::packet::integrity::IntegrityError;
+use crate::packet::signature::{Authenticity, NodeSigningKey, NodeVerifyingKey};
+
+/// Upper bound of SC notifications kept for re-sending.
+const MAX_PENDING_SC_EVENTS: usize = 10_000;
+
+/// What an SC notification is about, so that its delivery can be recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScNotificationSubject {
+    Packet(PacketID),
+    Message(MessageID),
+    Other,
+}
 
 pub struct Router {
     graph: NetGraph,
@@ -31,6 +57,28 @@ pub struct Router {
     inbound_api_command: Receiver<Command>,
     outbound_response_for_flood: Sender<ListOfDiscoveredEdgeNodes>,
     outbound_undread_messages: Sender<UnreadMessagesFromServer>,
+    neighbor_policy: NeighborPolicy,
+    neighbor_types: HashMap<NodeId, NodeType>,
+    sc_report_channel: Sender<ServiceEvent>,
+    api_response_channel: Option<Sender<ApiResponse>>,
+    housekeeping_ticker: Receiver<Instant>,
+    reassembly_timeout: Option<Duration>,
+    nack_abandoned_sessions: bool,
+    state_dump_path: Option<PathBuf>,
+    traffic_recorder: Option<TrafficRecorder>,
+    stats: Stats,
+    spans: TransmissionSpans,
+    pending_sc_events: VecDeque<(NodeEvent, ScNotificationSubject)>,
+    codec: Box<dyn FragmentCodec>,
+    message_encoding: MessageEncoding,
+    destination_encodings: HashMap<NodeId, MessageEncoding>,
+    compression_threshold: Option<usize>,
+    integrity_checks: bool,
+    peer_keys: HashMap<NodeId, PeerKey>,
+    signing_key: Option<NodeSigningKey>,
+    verifying_keys: HashMap<NodeId, NodeVerifyingKey>,
+    signature_policy: SignaturePolicy,
+    crashed: bool,
 }
 
 impl Router {
@@ -45,14 +93,31 @@ impl Router {
         inbound_sc_command_channel: Receiver<DroneCommand>,
         outbound_packet_channels: HashMap<NodeId, Sender<Packet>>,
         outbound_sc_event_channel: Sender<NodeEvent>,
+        sc_report_channel: Sender<ServiceEvent>,
         inbound_api_command: Receiver<Command>,
         outbound_response_for_flood: Sender<ListOfDiscoveredEdgeNodes>,
         outbound_undread_messages: Sender<UnreadMessagesFromServer>,
-    ) -> Self {
-        let graph = NetGraph::new(node_id);
-        let database = Database::new();
+        mut config: ServiceConfig,
+    ) -> Result<Self> {
+        let rng_seed = config.rng_seed.unwrap_or_else(rand::random);
+        info!("Client {node_id} uses random seed {rng_seed}");
+        let mut graph = NetGraph::with_seed(node_id, rng_seed);
+        let seeded_topology = config.initial_topology.is_some();
+        if let Some(topology) = &config.initial_topology {
+            graph.load_topology(topology)?;
+        }
+        let mut database = match config.initial_state.take() {
+            Some(snapshot) => Self::restore_database(node_id, snapshot, config.retention)?,
+            None => Database::with_retention_policy(node_id, config.retention),
+        };
+        database.set_clock(config.clock.clone());
+        let traffic_recorder = config
+            .traffic_log_path
+            .as_deref()
+            .map(|path| TrafficRecorder::create(path, config.clock.clone()))
+            .transpose()?;
 
-        Router {
+        let mut router = Router {
             session_id: 0,
             graph,
             node_id,
@@ -64,11 +129,52 @@ impl Router {
             inbound_api_command,
             outbound_response_for_flood,
             outbound_undread_messages,
+            neighbor_policy: config.neighbor_policy,
+            neighbor_types: config.neighbor_types,
+            sc_report_channel,
+            api_response_channel: config.api_response_channel,
+            housekeeping_ticker: config.clock.ticker(config.housekeeping_interval),
+            reassembly_timeout: config.reassembly_timeout,
+            nack_abandoned_sessions: config.nack_abandoned_sessions,
+            state_dump_path: config.state_dump_path,
+            traffic_recorder,
+            stats: Stats::default(),
+            spans: TransmissionSpans::default(),
+            pending_sc_events: VecDeque::new(),
+            codec: config.codec.build(),
+            message_encoding: config.message_encoding,
+            destination_encodings: config.destination_encodings,
+            compression_threshold: config.compression_threshold,
+            integrity_checks: config.integrity_checks,
+            peer_keys: config.peer_keys,
+            signing_key: config.signing_key,
+            verifying_keys: config.verifying_keys,
+            signature_policy: config.signature_policy,
+            crashed: false,
+        };
+        if seeded_topology {
+            router.notify_sc_of_known_topology();
+        }
+        Ok(router)
+    }
+
+    fn restore_database(
+        node_id: u8,
+        snapshot: DatabaseSnapshot,
+        retention: RetentionPolicy,
+    ) -> Result<Database> {
+        if snapshot.node_id != node_id {
+            return Err(anyhow!(
+                "Tried to restore a database of client {} to client {node_id}!",
+                snapshot.node_id
+            ));
         }
+        Database::from_snapshot(snapshot, retention)
     }
 
-    pub fn listen_channels(&mut self) -> !{
-        loop {
+    /// Listens to all inbound channels until the SC commands the client to crash.
+    pub fn listen_channels(&mut self) {
+        while !self.crashed {
             select! {
 
                 recv(self.inbound_packet_channel) -> packet => {
@@ -117,8 +223,15 @@ impl Router {
                     }
                 },
 
+                recv(self.housekeeping_ticker) -> _ => {
+                    if let Err(e) = self.run_housekeeping() {
+                        error!("Housekeeping failed with error: {e}");
+                    }
+                },
+
             }
         }
+        info!("Client {} stopped listening to channels.", self.node_id);
     }
 
     fn send_message(&mut self, message: &mut Message) -> Result<()> {
@@ -131,17 +244,77 @@ impl Router {
         })?.with_context(||"")?;
         let routing_header = SourceRoutingHeader::new(hops, 1);
 
-        let packets = packet::utils::message_to_packets(message, &routing_header);
-        self.outbound_sc_event_channel
-            .send(NodeEvent::StartingMessageTransmission(message.clone()))?;
+        let packets = packet::utils::message_to_packets(
+            message,
+            &routing_header,
+            self.codec.as_ref(),
+            &self.get_encode_options(destination),
+        )?;
+        self.spans.started(
+            message.session_id,
+            destination,
+            &routing_header.hops,
+            packets.len(),
+        );
+        self.notify_sc(
+            NodeEvent::StartingMessageTransmission(message.clone()),
+            ScNotificationSubject::Other,
+        );
         self.database.save_message(message);
         for packet in packets {
-            self.database.save_packet(packet.clone())?;
-            self.send_packet(packet)?;
+            let sent = self
+                .database
+                .save_packet(packet.clone())
+                .and_then(|()| self.send_packet(packet));
+            if let Err(e) = sent {
+                self.spans.abandoned(message.session_id);
+                return Err(e);
+            }
         }
+        self.stats.record_message_sent(&routing_header.hops);
         Ok(())
     }
 
+    /// Returns how messages for `destination` are serialized.
+    fn get_encode_options(&self, destination: NodeId) -> EncodeOptions {
+        let encoding = self
+            .destination_encodings
+            .get(&destination)
+            .copied()
+            .unwrap_or(self.message_encoding);
+        EncodeOptions {
+            encoding,
+            compression_threshold: self.compression_threshold,
+            checksum: self.integrity_checks,
+            encryption_key: self.peer_keys.get(&destination).cloned(),
+            signing_key: self.signing_key.clone(),
+        }
+    }
+
+    /// Returns how messages from `sender` are parsed.
+    fn get_decode_options(&self, sender: NodeId) -> DecodeOptions {
+        DecodeOptions {
+            decryption_key: self.peer_keys.get(&sender).cloned(),
+            verifying_key: self.verifying_keys.get(&sender).copied(),
+        }
+    }
+
+    pub fn set_peer_key(&mut self, peer: NodeId, key: PeerKey) {
+        self.peer_keys.insert(peer, key);
+    }
+
+    pub fn remove_peer_key(&mut self, peer: NodeId) -> Option<PeerKey> {
+        self.peer_keys.remove(&peer)
+    }
+
+    pub fn register_verifying_key(&mut self, node: NodeId, key: NodeVerifyingKey) {
+        self.verifying_keys.insert(node, key);
+    }
+
+    pub fn remove_verifying_key(&mut self, node: NodeId) -> Option<NodeVerifyingKey> {
+        self.verifying_keys.remove(&node)
+    }
+
     fn get_edge_nodes(&self) -> Option<Vec<(NodeId, NodeType)>> {
         self.graph.get_edge_nodes()
     }
@@ -150,21 +323,198 @@ impl Router {
         match command {
             DroneCommand::RemoveSender(node_id) => {
                 info!("Received SC command to remove {node_id} from neighbors.");
+                let sc_command = ControllerCommand::RemoveSender(*node_id);
+                if let Err(reason) = self.check_neighbor_removal(*node_id) {
+                    warn!("Rejected SC command to remove {node_id} from neighbors: {reason}");
+                    return self.report_to_sc(ServiceEvent::CommandRejected(sc_command, reason));
+                }
                 self.outbound_packet_channels.remove(node_id);
                 info!("{node_id} removed from neighbors.");
+                self.report_to_sc(ServiceEvent::CommandAccepted(sc_command))?;
                 self.flood_network()?;
             }
             DroneCommand::AddSender(node_id, channel) => {
                 info!("Received SC command to add {node_id} to neighbors.");
+                let sc_command = ControllerCommand::AddSender(*node_id);
+                if let Err(reason) = self.check_neighbor_addition(*node_id) {
+                    warn!("Rejected SC command to add {node_id} to neighbors: {reason}");
+                    return self.report_to_sc(ServiceEvent::CommandRejected(sc_command, reason));
+                }
                 self.outbound_packet_channels
                     .insert(*node_id, channel.clone());
+                self.report_to_sc(ServiceEvent::CommandAccepted(sc_command))?;
                 self.flood_network()?;
             }
-            _ => {}
+            DroneCommand::SetPacketDropRate(pdr) => {
+                // Clients do not drop packets, only drones do.
+                let reason = String::from("Packet drop rate can only be set for drones.");
+                warn!("Rejected SC command to set packet drop rate to {pdr}: {reason}");
+                self.report_to_sc(ServiceEvent::CommandRejected(
+                    ControllerCommand::SetPacketDropRate(*pdr),
+                    reason,
+                ))?;
+            }
+            DroneCommand::Crash => {
+                info!("Received SC command to crash. Shutting down.");
+                // The client stops even if the shutdown is not orderly, so
+                // the outcome is reported either way.
+                let outcome = self.shutdown();
+                let event = match &outcome {
+                    Ok(()) => ServiceEvent::CommandAccepted(ControllerCommand::Crash),
+                    Err(e) => ServiceEvent::CommandFailed(ControllerCommand::Crash, e.to_string()),
+                };
+                self.report_to_sc(event)?;
+                outcome?;
+            }
         }
         Ok(())
     }
 
+    /// Stops the client in an orderly manner.
+    ///
+    /// Packets already queued in the inbound channel are processed, the
+    /// final known topology is sent to the SC and the database state is
+    /// dumped if a dump path is configured before the main loop exits.
+    fn shutdown(&mut self) -> Result<()> {
+        self.crashed = true;
+        while let Ok(packet) = self.inbound_packet_channel.try_recv() {
+            if let Err(e) = self.process(packet.clone()) {
+                error!(
+                    "Tried to process packet {packet:?} during shutdown but failed with error: {e}"
+                );
+            }
+        }
+        self.notify_sc_of_known_topology();
+        if !self.pending_sc_events.is_empty() {
+            warn!(
+                "Shutting down with {} undelivered SC notifications.",
+                self.pending_sc_events.len()
+            );
+        }
+        if let Some(path) = &self.state_dump_path {
+            let json = self.database.export_snapshot().to_json()?;
+            std::fs::write(path, json)
+                .with_context(|| format!("Failed to dump database state to {}!", path.display()))?;
+            info!("Dumped database state to {}.", path.display());
+        }
+        Ok(())
+    }
+
+    /// Checks that adding `node_id` as a neighbor complies with the neighbor policy.
+    fn check_neighbor_addition(&self, node_id: NodeId) -> Result<(), String> {
+        if node_id == self.node_id {
+            return Err(String::from("Own ID is used as a recipient."));
+        }
+        let amount = if self.outbound_packet_channels.contains_key(&node_id) {
+            self.outbound_packet_channels.len()
+        } else {
+            self.outbound_packet_channels.len() + 1
+        };
+        self.neighbor_policy.validate_amount(amount)?;
+        if let Some(node_type) = self.get_known_node_type(node_id) {
+            self.neighbor_policy
+                .validate_node_type(node_id, node_type)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that removing `node_id` from neighbors complies with the neighbor policy.
+    fn check_neighbor_removal(&self, node_id: NodeId) -> Result<(), String> {
+        if !self.outbound_packet_channels.contains_key(&node_id) {
+            return Err(format!("{node_id} is not a neighbor."));
+        }
+        self.neighbor_policy
+            .validate_amount(self.outbound_packet_channels.len() - 1)
+    }
+
+    /// Returns the node type of `node_id` if it is configured or already discovered.
+    fn get_known_node_type(&self, node_id: NodeId) -> Option<NodeType> {
+        self.neighbor_types
+            .get(&node_id)
+            .copied()
+            .or_else(|| self.graph.get_node_type(node_id).ok())
+    }
+
+    /// Performs periodic maintenance: re-sends missed SC notifications,
+    /// abandons stale inbound sessions and enforces message retention,
+    /// closing the spans of evicted outbound sessions.
+    pub fn run_housekeeping(&mut self) -> Result<()> {
+        self.flush_pending_sc_events();
+        self.database.enforce_message_retention();
+        let (database, node_id) = (&self.database, self.node_id);
+        self.spans.retain(|session_id| {
+            database
+                .get_message_meta(MessageID(SessionID(session_id), SenderID(node_id)))
+                .is_some()
+        });
+        let Some(reassembly_timeout) = self.reassembly_timeout else {
+            return Ok(());
+        };
+        for session in self.database.evict_stale_sessions(reassembly_timeout) {
+            warn!(
+                "Abandoned session {} from {} after receiving {}/{} fragments.",
+                session.session_id,
+                session.sender_id,
+                session.received_amount_of_frags,
+                session.total_amount_of_frags
+            );
+            if self.nack_abandoned_sessions {
+                self.request_retransmission(&session)?;
+            }
+            self.report_to_sc(ServiceEvent::MessageAbandoned {
+                session_id: session.session_id,
+                sender_id: session.sender_id,
+                received_fragments: session.received_amount_of_frags,
+                total_fragments: session.total_amount_of_frags,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// NACKs every fragment of `session`, which makes the sender re-send the
+    /// whole message.
+    ///
+    /// `Dropped` is the only NACK type after which a sender simply re-sends
+    /// the fragment, without flooding or distrusting a route. The NACKs
+    /// originate from this client, and senders only count drops of drones,
+    /// so no node of the route is blamed for the lost fragments.
+    fn request_retransmission(&mut self, session: &AbandonedSession) -> Result<()> {
+        let Some(routing_header) = &session.routing_header else {
+            return Ok(());
+        };
+        let routing_header = packet::utils::get_reversed_route(routing_header);
+        for fragment_index in 0..session.total_amount_of_frags {
+            let nack = Packet {
+                routing_header: routing_header.clone(),
+                session_id: session.session_id,
+                pack_type: PacketType::Nack(Nack {
+                    fragment_index,
+                    nack_type: NackType::Dropped,
+                }),
+            };
+            self.send_packet(nack)?;
+        }
+        Ok(())
+    }
+
+    fn send_api_response(&self, response: ApiResponse) -> Result<()> {
+        let channel = self
+            .api_response_channel
+            .as_ref()
+            .with_context(|| "Tried to answer a command but no API response channel is set!")?;
+        channel
+            .send(response)
+            .with_context(|| "Failed to send a response to the front-end!")?;
+        Ok(())
+    }
+
+    fn report_to_sc(&self, event: ServiceEvent) -> Result<()> {
+        self.sc_report_channel
+            .send(event)
+            .with_context(|| "Failed to report an event to SC!")?;
+        Ok(())
+    }
+
     fn process_api_command(&mut self, command: Command) -> Result<()> {
         match command {
             Command::GetEdgeNodesFromFlood => {
@@ -176,24 +526,111 @@ impl Router {
             }
             Command::InitializeFlood => self.flood_network()?,
             Command::SendMessage(mut message) => {
-                self.send_message(&mut message)?;
+                if let Err(e) = self.send_message(&mut message) {
+                    self.stats.messages_failed += 1;
+                    return Err(e);
+                }
             }
 
-            Command::GetUnreadMessagesFromServer => {
-                if let Some(unread_message_ids) = self
-                    .database
-                    .get_undread_message_ids_from_server(self.node_id)
-                {
+            Command::GetUnreadMessagesFromServer(server_id) => {
+                let unread_message_ids = self.database.get_unread_message_ids(Some(server_id));
+                if !unread_message_ids.is_empty() {
                     let mut unread_messages = vec![];
                     for id in unread_message_ids {
                         if let Some(message) = self.database.get_message(id) {
                             unread_messages.push(message);
                         }
+                        // Messages handed over with this command count as read.
+                        self.database.update_message_to_read(id)?;
                     }
                     self.outbound_undread_messages
                         .send(backend::UnreadMessagesFromServer(unread_messages))?;
                 }
             }
+            Command::ListMessages {
+                peer,
+                offset,
+                limit,
+            } => {
+                let (message_ids, total) = self.database.list_message_ids(peer, offset, limit);
+                let mut entries = vec![];
+                for id in message_ids {
+                    if let Some(message) = self.database.get_message(id) {
+                        let read =
+                            id.1 == SenderID(self.node_id) || self.database.is_message_read(id);
+                        entries.push(InboxEntry { id, message, read });
+                    }
+                }
+                self.send_api_response(ApiResponse::Inbox(InboxPage {
+                    entries,
+                    offset,
+                    total,
+                }))?;
+            }
+            Command::MarkAsRead(message_id) => self.database.update_message_to_read(message_id)?,
+            Command::MarkAsUnread(message_id) => {
+                self.database.update_message_to_unread(message_id)?;
+            }
+            Command::GetUnreadCounts => {
+                let counts = self.database.get_unread_counts();
+                self.send_api_response(ApiResponse::UnreadCounts(UnreadCounts(counts)))?;
+            }
+            Command::GetConversation(peer) => {
+                let mut entries = vec![];
+                for id in self.database.get_conversation(peer) {
+                    let message = self.database.get_message(id);
+                    let meta = self.database.get_message_meta(id);
+                    if let (Some(message), Some(meta)) = (message, meta) {
+                        let read =
+                            id.1 == SenderID(self.node_id) || self.database.is_message_read(id);
+                        entries.push(TranscriptEntry {
+                            id,
+                            message,
+                            meta,
+                            read,
+                        });
+                    }
+                }
+                self.send_api_response(ApiResponse::Transcript(Transcript { peer, entries }))?;
+            }
+            Command::LoadTopology(topology) => {
+                self.graph.load_topology(&topology)?;
+                self.notify_sc_of_known_topology();
+            }
+            Command::GetTopology => {
+                let topology = self.graph.to_known_topology();
+                self.send_api_response(ApiResponse::Topology(topology))?;
+            }
+            Command::GetStats => {
+                self.send_api_response(ApiResponse::Stats(self.stats))?;
+            }
+            Command::GetMemoryStats => {
+                let stats = self.database.get_memory_stats();
+                self.send_api_response(ApiResponse::MemoryStats(stats))?;
+            }
+            Command::GetSessionStatus {
+                session_id,
+                sender_id,
+            } => {
+                let status = self.database.get_session_status(session_id, sender_id);
+                self.send_api_response(ApiResponse::SessionStatus(status))?;
+            }
+            Command::ListSessionStatuses => {
+                let statuses = self.database.get_session_statuses();
+                self.send_api_response(ApiResponse::SessionStatuses(statuses))?;
+            }
+            Command::SetPeerKey { peer, key } => self.set_peer_key(peer, key),
+            Command::RemovePeerKey(peer) => {
+                self.remove_peer_key(peer);
+            }
+            Command::RegisterVerifyingKey { node, key } => self.register_verifying_key(node, key),
+            Command::RemoveVerifyingKey(node) => {
+                self.remove_verifying_key(node);
+            }
+            Command::ExportState => {
+                let snapshot = self.database.export_snapshot();
+                self.send_api_response(ApiResponse::State(snapshot))?;
+            }
             // TODO this could be removed
             Command::GetClientsFromServer(_server_id) => {}
         }
@@ -202,22 +639,27 @@ impl Router {
         //     }
     }
 
-    fn flood_network(&self) -> Result<()> {
+    fn flood_network(&mut self) -> Result<()> {
         let packet = packet::utils::get_new_flood_request_packet(self.session_id, self.node_id);
-        for neighbor in &self.outbound_packet_channels {
-            neighbor.1.send(packet.clone()).with_context(|| {
-                format!("Failed to send flood packet to neighbor {}.", neighbor.0)
-            })?;
-            self.outbound_sc_event_channel
-                .send(NodeEvent::PacketSent(packet.clone()))
-                .with_context(
-                    || "Failed to send packet to SC after sending packet to a neighbor!",
-                )?;
+        self.stats.floods_started += 1;
+        let neighbors: Vec<NodeId> = self.outbound_packet_channels.keys().copied().collect();
+        for neighbor in neighbors {
+            if let Some(channel) = self.outbound_packet_channels.get(&neighbor) {
+                channel.send(packet.clone()).with_context(|| {
+                    format!("Failed to send flood packet to neighbor {neighbor}.")
+                })?;
+                self.record_traffic(TrafficDirection::Outbound, &packet);
+                self.stats.record_sent(&packet.pack_type);
+            }
+            self.notify_sc(
+                NodeEvent::PacketSent(packet.clone()),
+                ScNotificationSubject::Other,
+            );
         }
         Ok(())
     }
 
-    fn send_packet(&self, packet: Packet) -> Result<()> {
+    fn send_packet(&mut self, packet: Packet) -> Result<()> {
         let neighbor = packet
             .routing_header
             .hops
@@ -234,13 +676,105 @@ impl Router {
         neighbor_channel
             .send(packet.clone())
             .with_context(|| format!("Failed to send packet to neighbor {neighbor}."))?;
-        self.outbound_sc_event_channel
-            .send(NodeEvent::PacketSent(packet))
-            .with_context(|| "Failed to send packet to SC after sending packet to a neighbor!")?;
+        self.record_traffic(TrafficDirection::Outbound, &packet);
+        self.stats.record_sent(&packet.pack_type);
+        if self.database.record_fragment_transfer(&packet) {
+            self.stats.retransmissions += 1;
+        }
+
+        let subject = match (&packet.pack_type, packet.routing_header.source()) {
+            (PacketType::MsgFragment(fragment), Some(sender_id)) => {
+                if sender_id == self.node_id {
+                    self.spans.fragment_sent(
+                        packet.session_id,
+                        fragment.fragment_index,
+                        &packet.routing_header.hops,
+                    );
+                }
+                ScNotificationSubject::Packet(PacketID(
+                    SessionID(packet.session_id),
+                    SenderID(sender_id),
+                    FragmentID(fragment.fragment_index),
+                ))
+            }
+            _ => ScNotificationSubject::Other,
+        };
+        self.notify_sc(NodeEvent::PacketSent(packet), subject);
         Ok(())
     }
 
-    fn get_route_to_node(&self, destination_node: u8) -> Result<Option<Vec<u8>>> {
+    /// Sends an event to the SC and records the notification in the database.
+    ///
+    /// If the SC channel is full or disconnected the event is queued and
+    /// re-sent later, keeping the order of notifications.
+    fn notify_sc(&mut self, event: NodeEvent, subject: ScNotificationSubject) {
+        self.flush_pending_sc_events();
+        if !self.pending_sc_events.is_empty() {
+            self.queue_sc_event(event, subject);
+            return;
+        }
+        match self.outbound_sc_event_channel.try_send(event) {
+            Ok(()) => self.record_sc_notification(subject),
+            Err(e) => {
+                warn!("Failed to notify SC, the notification will be re-sent. Error: {e}");
+                self.queue_sc_event(e.into_inner(), subject);
+            }
+        }
+    }
+
+    fn queue_sc_event(&mut self, event: NodeEvent, subject: ScNotificationSubject) {
+        if self.pending_sc_events.len() >= MAX_PENDING_SC_EVENTS {
+            error!("Too many pending SC notifications, dropping the oldest one.");
+            self.pending_sc_events.pop_front();
+        }
+        self.pending_sc_events.push_back((event, subject));
+    }
+
+    /// Re-sends queued SC notifications until the queue is empty or sending fails.
+    fn flush_pending_sc_events(&mut self) {
+        while let Some((event, subject)) = self.pending_sc_events.pop_front() {
+            if let Err(e) = self.outbound_sc_event_channel.try_send(event) {
+                self.pending_sc_events.push_front((e.into_inner(), subject));
+                break;
+            }
+            self.record_sc_notification(subject);
+        }
+    }
+
+    fn record_sc_notification(&mut self, subject: ScNotificationSubject) {
+        let result = match subject {
+            ScNotificationSubject::Packet(packet_id) => self
+                .database
+                .update_packet_sent_to_simulation_controller(packet_id),
+            ScNotificationSubject::Message(message_id) => self
+                .database
+                .update_message_sent_to_simulation_controller(message_id),
+            ScNotificationSubject::Other => Ok(()),
+        };
+        if let Err(e) = result {
+            warn!("Notified SC but failed to record the notification. Error: {e}");
+        }
+    }
+
+    /// Returns whether the SC has been or is about to be notified of `subject`.
+    fn is_sc_notified(&self, subject: ScNotificationSubject) -> bool {
+        let delivered = match subject {
+            ScNotificationSubject::Packet(packet_id) => {
+                self.database.is_packet_sent_to_sc(packet_id)
+            }
+            ScNotificationSubject::Message(message_id) => {
+                self.database.is_message_sent_to_sc(message_id)
+            }
+            ScNotificationSubject::Other => false,
+        };
+        delivered
+            || self
+                .pending_sc_events
+                .iter()
+                .any(|(_, pending)| *pending == subject)
+    }
+
+    fn get_route_to_node(&mut self, destination_node: u8) -> Result<Option<Vec<u8>>> {
         let from = Vertice::new((self.node_id, NodeType::Client));
         let node_type = self.graph.get_node_type(destination_node)?;
         let to = Vertice::new((destination_node, node_type));
@@ -248,10 +782,30 @@ impl Router {
     }
 
     fn add_route(&mut self, route: &[(u8, NodeType)]) -> Result<()> {
-        self.graph.add_route(route, &self.outbound_sc_event_channel)
+        self.graph.add_route(route)?;
+        self.notify_sc_of_known_topology();
+        Ok(())
+    }
+
+    fn notify_sc_of_known_topology(&mut self) {
+        let event = self.graph.known_network_graph_event();
+        self.notify_sc(event, ScNotificationSubject::Other);
+    }
+
+    /// Logs `packet` if traffic recording is enabled. Failing to record
+    /// does not stop the packet from being handled.
+    fn record_traffic(&mut self, direction: TrafficDirection, packet: &Packet) {
+        let Some(recorder) = &mut self.traffic_recorder else {
+            return;
+        };
+        if let Err(e) = recorder.record(direction, packet) {
+            error!("Failed to record {direction:?} packet: {e}");
+        }
     }
 
     fn process(&mut self, packet: Packet) -> Result<()> {
+        self.record_traffic(TrafficDirection::Inbound, &packet);
+        self.stats.record_received(&packet.pack_type);
         match packet.pack_type {
             PacketType::MsgFragment(_) => self.process_fragment(&packet)?,
             PacketType::Ack(_) => self.process_ack(&packet)?,
@@ -270,7 +824,19 @@ impl Router {
         let PacketType::MsgFragment(fragment) = &packet.pack_type else {
             return Err(anyhow!("Packet is not Fragment! Packet: {packet:?}"));
         };
+        let evicted = packet.routing_header.source().is_some_and(|sender_id| {
+            self.database
+                .is_session_evicted(packet.session_id, sender_id)
+        });
+        if evicted {
+            info!(
+                "Ignored a late fragment of session {}, its message was already evicted.",
+                packet.session_id
+            );
+            return Ok(());
+        }
         self.database.save_packet(packet.clone())?;
+        self.database.record_fragment_transfer(packet);
         let session_id = packet.session_id;
         let total_amount_of_frags = fragment.total_n_fragments;
         let sender_id = packet.routing_header.source().with_context(|| {
@@ -286,25 +852,111 @@ impl Router {
             "Failed to query amount of fragments for session {session_id} from sender {sender_id}"
         )
     })?;
+        // Duplicates of fragments of an already reassembled message are ignored.
+        let message_id = MessageID(SessionID(session_id), SenderID(sender_id));
+        let already_reassembled = self.database.get_message(message_id).is_some();
         //     fetch packets
-        if amount_of_frags_received == total_amount_of_frags {
+        if amount_of_frags_received == total_amount_of_frags && !already_reassembled {
             let packets = self.database.get_packets_for_session(session_id, sender_id);
             let packets = packets.with_context(
                 || "Received all fragments but failed to fetch them to build a message",
             )?;
             //     build message
-            let message = packet::utils::packets_to_message(&packets)?;
+            let (message, authenticity) = match packet::utils::packets_to_authenticated_message(
+                &packets,
+                self.codec.as_ref(),
+                &self.get_decode_options(sender_id),
+            ) {
+                Ok(decoded) => decoded,
+                Err(e) if e.downcast_ref::<IntegrityError>().is_some() => {
+                    return self.discard_corrupted_session(session_id, sender_id, &e);
+                }
+                Err(e) => return Err(e),
+            };
+            // A valid signature only vouches for the node that sent the fragments.
+            let authenticity = if message.source == sender_id {
+                authenticity
+            } else {
+                Authenticity::Forged
+            };
+            if !self.check_authenticity(session_id, sender_id, authenticity)? {
+                return Ok(());
+            }
             //     save message do db
             self.database.save_message(&message);
-            self.outbound_sc_event_channel
-                .send(NodeEvent::MessageReceived(message.clone()))
-                .with_context(|| {
-                    format!("Received a message but failed to send it to SC. Message: {message:?}",)
-                })?;
+            if authenticity != Authenticity::Verified
+                && self.signature_policy == SignaturePolicy::Flag
+            {
+                self.database.flag_message_unverified(MessageID(
+                    SessionID(message.session_id),
+                    SenderID(message.source),
+                ));
+            }
+            self.database.message_reassembled(session_id, sender_id);
+            if let Some(reassembly_millis) =
+                self.database.record_session_finished(session_id, sender_id)
+            {
+                self.stats.record_message_received(reassembly_millis);
+            }
+            let subject = ScNotificationSubject::Message(message_id);
+            if !self.is_sc_notified(subject) {
+                self.notify_sc(NodeEvent::MessageReceived(message), subject);
+            }
         }
         Ok(())
     }
 
+    /// Applies the signature policy to a reassembled message and reports
+    /// messages whose sender could not be authenticated.
+    ///
+    /// Returns whether the message is kept.
+    fn check_authenticity(
+        &mut self,
+        session_id: u64,
+        sender_id: NodeId,
+        authenticity: Authenticity,
+    ) -> Result<bool> {
+        if self.signature_policy == SignaturePolicy::Ignore
+            || authenticity == Authenticity::Verified
+        {
+            return Ok(true);
+        }
+        let rejected = self.signature_policy == SignaturePolicy::Require;
+        warn!(
+            "Message of session {session_id} from {sender_id} is {authenticity:?}{}",
+            if rejected { ", discarding it." } else { "." }
+        );
+        if rejected {
+            self.database.discard_session(session_id, sender_id);
+        }
+        self.report_to_sc(ServiceEvent::UnverifiedMessage {
+            session_id,
+            sender_id,
+            authenticity,
+            rejected,
+        })?;
+        Ok(!rejected)
+    }
+
+    /// Drops the fragments of a message that failed its integrity check and
+    /// asks the sender to retransmit it.
+    fn discard_corrupted_session(
+        &mut self,
+        session_id: u64,
+        sender_id: NodeId,
+        error: &anyhow::Error,
+    ) -> Result<()> {
+        warn!("Discarding message of session {session_id} from {sender_id}: {error}");
+        if let Some(session) = self.database.discard_session(session_id, sender_id) {
+            self.request_retransmission(&session)?;
+        }
+        self.report_to_sc(ServiceEvent::MessageCorrupted {
+            session_id,
+            sender_id,
+            reason: error.to_string(),
+        })
+    }
+
     fn process_ack(&mut self, packet: &Packet) -> Result<()> {
         let PacketType::Ack(ack) = &packet.pack_type else {
             return Err(anyhow!("Packet is not ACK! Packet: {packet:?}"));
@@ -315,6 +967,8 @@ impl Router {
             SenderID(self.node_id),
             FragmentID(ack.fragment_index),
         );
+        self.spans
+            .fragment_acked(packet.session_id, ack.fragment_index);
         self.database.update_packet_ack_received(packet_id)?;
         let message_fully_sent = self
             .database
@@ -326,15 +980,27 @@ impl Router {
             let message_id = MessageID(SessionID(packet.session_id), SenderID(self.node_id));
             let message = self.database.get_message(message_id);
             let message = message.with_context(|| format!("All packets have been ACKed for session {} but did not find message for such a session!", packet_id.0.0))?;
+            self.database
+                .message_acknowledged(packet_id.0.0, packet_id.1.0);
+            if self
+                .database
+                .record_session_finished(packet_id.0.0, packet_id.1.0)
+                .is_some()
+            {
+                self.stats.messages_delivered += 1;
+                self.spans.completed(packet.session_id);
+            }
 
-            self.outbound_sc_event_channel
-            .send(NodeEvent::MessageSentSuccessfully(message))
-            .with_context(|| format!("Received final ACK for session {}. Failed to send SC a copy of the sent message!", packet_id.0.0))?;
+            // A duplicate final ACK must not notify the SC again.
+            let subject = ScNotificationSubject::Message(message_id);
+            if !self.is_sc_notified(subject) {
+                self.notify_sc(NodeEvent::MessageSentSuccessfully(message), subject);
+            }
         }
         Ok(())
     }
 
-    fn process_nack(&self, packet: Packet) -> Result<()> {
+    fn process_nack(&mut self, packet: Packet) -> Result<()> {
         let PacketType::Nack(nack) = packet.pack_type else {
             return Err(anyhow!("Packet is not NACK! Packet: {packet:?}"));
         };
@@ -344,6 +1010,21 @@ impl Router {
             SenderID(self.node_id),
             FragmentID(nack.fragment_index),
         );
+        self.database
+            .record_fragment_nacked(packet_id.0.0, packet_id.1.0);
+        self.spans
+            .fragment_nacked(packet.session_id, nack.fragment_index, &nack.nack_type);
+        // Only drones drop packets. A `Dropped` NACK from a client or server
+        // asks for a retransmission, see `request_retransmission`.
+        let dropped_by = match nack.nack_type {
+            NackType::Dropped => packet.routing_header.source(),
+            _ => None,
+        };
+        if let Some(dropped_by) = dropped_by
+            .filter(|node_id| matches!(self.get_known_node_type(*node_id), Some(NodeType::Drone)))
+        {
+            self.graph.record_dropped_packet(dropped_by);
+        }
 
         let Some(mut packet) = self.database.get_packet(packet_id) else {
             return Err(anyhow!("Failed to fetch packet from database!",));
@@ -0,0 +1,169 @@
+//! Structured events following a message transmission from its first
+//! fragment to its completion.
+//!
+//! With the `tracing` feature every outbound session gets a `tracing` span
+//! carrying its `session_id`, `destination` and `route`, and fragment events
+//! are emitted inside it. Without the feature the same events are written as
+//! `log` lines with `key=value` fields.
+
+#[cfg(feature = "tracing")]
+use std::collections::HashMap;
+
+use wg_2024::network::NodeId;
+use wg_2024::packet::NackType;
+
+/// Spans of the outbound sessions that have not completed yet.
+#[derive(Debug, Default)]
+pub(crate) struct TransmissionSpans {
+    #[cfg(feature = "tracing")]
+    spans: HashMap<u64, tracing::Span>,
+}
+
+#[cfg(feature = "tracing")]
+impl TransmissionSpans {
+    pub fn started(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        route: &[NodeId],
+        total_fragments: usize,
+    ) {
+        let span = tracing::info_span!(
+            "message_transmission",
+            session_id,
+            destination,
+            route = ?route,
+            total_fragments,
+        );
+        span.in_scope(|| tracing::info!("transmission started"));
+        self.spans.insert(session_id, span);
+    }
+
+    pub fn fragment_sent(&self, session_id: u64, fragment_index: u64, route: &[NodeId]) {
+        self.in_span(session_id, || {
+            tracing::debug!(fragment_index, route = ?route, "fragment sent");
+        });
+    }
+
+    pub fn fragment_acked(&self, session_id: u64, fragment_index: u64) {
+        self.in_span(session_id, || {
+            tracing::debug!(fragment_index, "fragment acked");
+        });
+    }
+
+    pub fn fragment_nacked(&self, session_id: u64, fragment_index: u64, nack_type: &NackType) {
+        self.in_span(session_id, || {
+            tracing::warn!(fragment_index, nack_type = ?nack_type, "fragment nacked");
+        });
+    }
+
+    /// Closes the span of a session whose fragments have all been ACKed.
+    pub fn completed(&mut self, session_id: u64) {
+        if let Some(span) = self.spans.remove(&session_id) {
+            span.in_scope(|| tracing::info!("transmission completed"));
+        }
+    }
+
+    /// Closes the span of a session that will not complete, e.g. because
+    /// sending its fragments failed.
+    pub fn abandoned(&mut self, session_id: u64) {
+        if let Some(span) = self.spans.remove(&session_id) {
+            span.in_scope(|| tracing::warn!("transmission abandoned"));
+        }
+    }
+
+    /// Abandons the sessions for which `is_tracked` returns false, e.g.
+    /// because their message was evicted.
+    pub fn retain(&mut self, mut is_tracked: impl FnMut(u64) -> bool) {
+        let evicted: Vec<u64> = self
+            .spans
+            .keys()
+            .copied()
+            .filter(|session_id| !is_tracked(*session_id))
+            .collect();
+        for session_id in evicted {
+            self.abandoned(session_id);
+        }
+    }
+
+    fn in_span(&self, session_id: u64, event: impl FnOnce()) {
+        match self.spans.get(&session_id) {
+            Some(span) => span.in_scope(event),
+            None => tracing::info_span!("message_transmission", session_id).in_scope(event),
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl TransmissionSpans {
+    pub fn started(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        route: &[NodeId],
+        total_fragments: usize,
+    ) {
+        log::debug!(
+            "transmission started session_id={session_id} destination={destination} route={route:?} total_fragments={total_fragments}"
+        );
+    }
+
+    pub fn fragment_sent(&self, session_id: u64, fragment_index: u64, route: &[NodeId]) {
+        log::debug!(
+            "fragment sent session_id={session_id} fragment_index={fragment_index} route={route:?}"
+        );
+    }
+
+    pub fn fragment_acked(&self, session_id: u64, fragment_index: u64) {
+        log::debug!("fragment acked session_id={session_id} fragment_index={fragment_index}");
+    }
+
+    pub fn fragment_nacked(&self, session_id: u64, fragment_index: u64, nack_type: &NackType) {
+        log::debug!(
+            "fragment nacked session_id={session_id} fragment_index={fragment_index} nack_type={nack_type:?}"
+        );
+    }
+
+    pub fn completed(&mut self, session_id: u64) {
+        log::debug!("transmission completed session_id={session_id}");
+    }
+
+    pub fn abandoned(&mut self, session_id: u64) {
+        log::debug!("transmission abandoned session_id={session_id}");
+    }
+
+    /// Nothing is kept per session without the `tracing` feature.
+    pub fn retain(&mut self, _is_tracked: impl FnMut(u64) -> bool) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finished_sessions_drop_their_span() {
+        let mut spans = TransmissionSpans::default();
+        spans.started(1, 4, &[1, 2, 4], 2);
+        spans.started(2, 4, &[1, 2, 4], 1);
+        spans.completed(1);
+        spans.abandoned(2);
+        assert!(spans.spans.is_empty());
+
+        // Late events of a finished session do not revive its span.
+        spans.fragment_acked(1, 0);
+        assert!(spans.spans.is_empty());
+    }
+
+    #[test]
+    fn test_untracked_sessions_are_abandoned() {
+        let mut spans = TransmissionSpans::default();
+        for session_id in 0..4 {
+            spans.started(session_id, 4, &[1, 2, 4], 1);
+        }
+        spans.retain(|session_id| session_id % 2 == 0);
+
+        let mut remaining: Vec<u64> = spans.spans.keys().copied().collect();
+        remaining.sort_unstable();
+        assert_eq!(remaining, vec![0, 2]);
+    }
+}
@@ -0,0 +1,160 @@
+//! Counters describing the behavior of a client since it started.
+
+use serde::{Deserialize, Serialize};
+use wg_2024::packet::{NackType, PacketType};
+
+/// Amount of packets per packet type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacketCounts {
+    pub fragments: u64,
+    pub acks: u64,
+    pub nacks: u64,
+    pub flood_requests: u64,
+    pub flood_responses: u64,
+}
+
+impl PacketCounts {
+    fn count(&mut self, pack_type: &PacketType) {
+        let counter = match pack_type {
+            PacketType::MsgFragment(_) => &mut self.fragments,
+            PacketType::Ack(_) => &mut self.acks,
+            PacketType::Nack(_) => &mut self.nacks,
+            PacketType::FloodRequest(_) => &mut self.flood_requests,
+            PacketType::FloodResponse(_) => &mut self.flood_responses,
+        };
+        *counter += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.fragments + self.acks + self.nacks + self.flood_requests + self.flood_responses
+    }
+}
+
+/// Amount of NACKs per `NackType`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NackCounts {
+    pub error_in_routing: u64,
+    pub destination_is_drone: u64,
+    pub dropped: u64,
+    pub unexpected_recipient: u64,
+}
+
+impl NackCounts {
+    fn count(&mut self, nack_type: &NackType) {
+        let counter = match nack_type {
+            NackType::ErrorInRouting(_) => &mut self.error_in_routing,
+            NackType::DestinationIsDrone => &mut self.destination_is_drone,
+            NackType::Dropped => &mut self.dropped,
+            NackType::UnexpectedRecipient(_) => &mut self.unexpected_recipient,
+        };
+        *counter += 1;
+    }
+}
+
+/// Runtime statistics of a client, answered to `Command::GetStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    pub packets_sent: PacketCounts,
+    pub packets_received: PacketCounts,
+    pub nacks_received: NackCounts,
+    /// Fragments sent again after having been sent once.
+    pub retransmissions: u64,
+    pub floods_started: u64,
+    /// Messages whose fragments were all handed to a neighbor.
+    pub messages_sent: u64,
+    /// Sent messages whose fragments were all ACKed.
+    pub messages_delivered: u64,
+    /// Messages that could not be sent, e.g. because no route was known.
+    pub messages_failed: u64,
+    /// Messages reassembled from received fragments.
+    pub messages_received: u64,
+    /// Sum of the hop counts of the routes sent messages took.
+    pub total_route_length: u64,
+    /// Sum of the milliseconds between the first fragment of a received
+    /// message and its reassembly.
+    pub total_reassembly_millis: u64,
+}
+
+impl Stats {
+    pub(crate) fn record_received(&mut self, pack_type: &PacketType) {
+        self.packets_received.count(pack_type);
+        if let PacketType::Nack(nack) = pack_type {
+            self.nacks_received.count(&nack.nack_type);
+        }
+    }
+
+    pub(crate) fn record_sent(&mut self, pack_type: &PacketType) {
+        self.packets_sent.count(pack_type);
+    }
+
+    pub(crate) fn record_message_sent(&mut self, hops: &[u8]) {
+        self.messages_sent += 1;
+        self.total_route_length += hops.len().saturating_sub(1) as u64;
+    }
+
+    pub(crate) fn record_message_received(&mut self, reassembly_millis: u64) {
+        self.messages_received += 1;
+        self.total_reassembly_millis += reassembly_millis;
+    }
+
+    /// Average amount of hops of the routes sent messages took.
+    pub fn average_route_length(&self) -> Option<f64> {
+        average(self.total_route_length, self.messages_sent)
+    }
+
+    /// Average milliseconds it took to receive all fragments of a message.
+    pub fn average_reassembly_millis(&self) -> Option<f64> {
+        average(self.total_reassembly_millis, self.messages_received)
+    }
+}
+
+fn average(total: u64, count: u64) -> Option<f64> {
+    (count > 0).then(|| total as f64 / count as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
+    use pretty_assertions::assert_eq;
+    use wg_2024::packet::{Ack, Nack};
+
+    use super::*;
+
+    #[test]
+    fn test_received_nacks_are_counted_by_type() {
+        let mut stats = Stats::default();
+        stats.record_received(&PacketType::Ack(Ack { fragment_index: 0 }));
+        for nack_type in [
+            NackType::Dropped,
+            NackType::Dropped,
+            NackType::DestinationIsDrone,
+        ] {
+            stats.record_received(&PacketType::Nack(Nack {
+                fragment_index: 0,
+                nack_type,
+            }));
+        }
+
+        assert_eq!(stats.packets_received.acks, 1);
+        assert_eq!(stats.packets_received.nacks, 3);
+        assert_eq!(stats.packets_received.total(), 4);
+        assert_eq!(stats.nacks_received.dropped, 2);
+        assert_eq!(stats.nacks_received.destination_is_drone, 1);
+        assert_eq!(stats.nacks_received.error_in_routing, 0);
+    }
+
+    #[test]
+    fn test_averages() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.average_route_length(), None);
+        assert_eq!(stats.average_reassembly_millis(), None);
+
+        stats.record_message_sent(&[1, 2, 3, 4]);
+        stats.record_message_sent(&[1, 2]);
+        stats.record_message_received(30);
+        stats.record_message_received(10);
+
+        assert_eq!(stats.average_route_length(), Some(2.0));
+        assert_eq!(stats.average_reassembly_millis(), Some(20.0));
+    }
+}
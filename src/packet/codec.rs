@@ -0,0 +1,131 @@
+use anyhow::{Result, anyhow};
+use assembler::Assembler;
+use assembler::naive_assembler::NaiveAssembler;
+use wg_2024::packet::Fragment;
+
+use super::integrity;
+
+/// Splits a byte payload into fragments and joins fragments back into the payload.
+pub trait FragmentCodec: Send {
+    /// Splits `data` into fragments ordered by fragment index.
+    fn encode(&self, data: &[u8]) -> Vec<Fragment>;
+
+    /// Joins fragments of a single payload. The fragments may be in any order.
+    ///
+    /// # Errors
+    /// Returns an error if the fragments do not form a valid payload.
+    fn decode(&self, fragments: &[Fragment]) -> Result<Vec<u8>>;
+}
+
+/// Selects the `FragmentCodec` used by the `Service`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CodecKind {
+    /// Plain fragmentation, compatible with every other node.
+    #[default]
+    Naive,
+    /// Fragmentation with a header carrying the payload length and a CRC-32 checksum.
+    Checksum,
+}
+
+impl CodecKind {
+    pub fn build(self) -> Box<dyn FragmentCodec> {
+        match self {
+            CodecKind::Naive => Box::new(NaiveCodec),
+            CodecKind::Checksum => Box::new(ChecksumCodec),
+        }
+    }
+}
+
+/// Fragments payloads with `NaiveAssembler`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NaiveCodec;
+
+impl FragmentCodec for NaiveCodec {
+    fn encode(&self, data: &[u8]) -> Vec<Fragment> {
+        NaiveAssembler::disassemble(data)
+    }
+
+    fn decode(&self, fragments: &[Fragment]) -> Result<Vec<u8>> {
+        let mut fragments = fragments.to_vec();
+        fragments.sort_by_key(|fragment| fragment.fragment_index);
+        Ok(NaiveAssembler::reassemble(&fragments))
+    }
+}
+
+/// Prefixes payloads with a header before fragmenting them with `NaiveCodec`.
+///
+/// Header layout: two magic bytes, payload length as big-endian `u32` and
+/// CRC-32 of the payload as big-endian `u32`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChecksumCodec;
+
+impl ChecksumCodec {
+    const MAGIC: [u8; 2] = [0xC5, 0x01];
+    const HEADER_LENGTH: usize = 10;
+}
+
+impl FragmentCodec for ChecksumCodec {
+    fn encode(&self, data: &[u8]) -> Vec<Fragment> {
+        let length = u32::try_from(data.len()).unwrap_or(u32::MAX);
+        let mut framed = Vec::with_capacity(Self::HEADER_LENGTH + data.len());
+        framed.extend_from_slice(&Self::MAGIC);
+        framed.extend_from_slice(&length.to_be_bytes());
+        framed.extend_from_slice(&integrity::checksum(data).to_be_bytes());
+        framed.extend_from_slice(data);
+        NaiveCodec.encode(&framed)
+    }
+
+    fn decode(&self, fragments: &[Fragment]) -> Result<Vec<u8>> {
+        let framed = NaiveCodec.decode(fragments)?;
+        if framed.len() < Self::HEADER_LENGTH || framed[..2] != Self::MAGIC {
+            return Err(anyhow!("Payload does not start with a checksum header!"));
+        }
+        let (header, data) = framed.split_at(Self::HEADER_LENGTH);
+        let length = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+        let checksum = u32::from_be_bytes([header[6], header[7], header[8], header[9]]);
+
+        if usize::try_from(length).ok() != Some(data.len()) {
+            return Err(anyhow!(
+                "Payload length {} does not match length {length} in header!",
+                data.len()
+            ));
+        }
+        integrity::verify(data, checksum)?;
+        Ok(data.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    fn payload() -> Vec<u8> {
+        (0..=255).cycle().take(1000).collect()
+    }
+
+    #[test]
+    fn test_codecs_round_trip() {
+        for kind in [CodecKind::Naive, CodecKind::Checksum] {
+            let codec = kind.build();
+            let mut fragments = codec.encode(&payload());
+            assert!(fragments.len() > 1);
+            fragments.reverse();
+            assert_eq!(codec.decode(&fragments).unwrap(), payload());
+        }
+    }
+
+    #[test]
+    fn test_checksum_codec_detects_corruption() {
+        let mut fragments = ChecksumCodec.encode(&payload());
+        fragments[3].data[0] ^= 0xFF;
+        let error = ChecksumCodec.decode(&fragments).unwrap_err();
+        assert!(error.downcast_ref::<integrity::IntegrityError>().is_some());
+    }
+
+    #[test]
+    fn test_checksum_codec_detects_missing_header() {
+        let fragments = NaiveCodec.encode(&payload());
+        assert!(ChecksumCodec.decode(&fragments).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Authenticated encryption of payloads with keys shared between two nodes.
+
+use std::fmt;
+
+use anyhow::{Result, anyhow};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+
+const NONCE_LENGTH: usize = 12;
+
+/// A ChaCha20-Poly1305 key shared with a peer.
+///
+/// Keys are exchanged out of band, e.g. configured on both the client and
+/// the server before the simulation starts.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerKey(pub [u8; 32]);
+
+impl PeerKey {
+    /// Generates a random key.
+    pub fn generate() -> Self {
+        PeerKey(rand::random())
+    }
+}
+
+impl fmt::Debug for PeerKey {
+    /// Keys are never written to logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PeerKey(..)")
+    }
+}
+
+/// Encrypts `plaintext` with a random nonce, which is prepended to the
+/// returned ciphertext.
+///
+/// # Errors
+/// Returns an error if encryption fails.
+pub fn encrypt(key: &PeerKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key.0));
+    let nonce: [u8; NONCE_LENGTH] = rand::random();
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow!("Failed to encrypt payload!"))?;
+
+    let mut encrypted = Vec::with_capacity(NONCE_LENGTH + ciphertext.len());
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(encrypted)
+}
+
+/// Decrypts data produced by `encrypt`.
+///
+/// # Errors
+/// Returns an error if the data was not encrypted with `key` or was modified.
+pub fn decrypt(key: &PeerKey, encrypted: &[u8]) -> Result<Vec<u8>> {
+    if encrypted.len() < NONCE_LENGTH {
+        return Err(anyhow!("Encrypted payload is missing its nonce!"));
+    }
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key.0));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Failed to decrypt payload: wrong key or tampered data!"))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn test_encryption_round_trip() {
+        let key = PeerKey::generate();
+        let encrypted = encrypt(&key, b"secret").unwrap();
+        assert_ne!(&encrypted[NONCE_LENGTH..], b"secret");
+        assert_eq!(decrypt(&key, &encrypted).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_decryption_fails_with_wrong_key_or_tampering() {
+        let key = PeerKey::generate();
+        let mut encrypted = encrypt(&key, b"secret").unwrap();
+        assert!(decrypt(&PeerKey::generate(), &encrypted).is_err());
+
+        encrypted[NONCE_LENGTH] ^= 0x01;
+        assert!(decrypt(&key, &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_key_is_not_printed() {
+        assert_eq!(format!("{:?}", PeerKey([7; 32])), "PeerKey(..)");
+    }
+}
@@ -0,0 +1,485 @@
+//! Serialization of a `Message` into the payload that is split into fragments.
+//!
+//! By default the payload is the stringified message, which every node
+//! understands. Any other representation is wrapped in an envelope: a magic
+//! byte followed by a flags byte, an optional checksum over the whole
+//! envelope and the body. The magic byte is never the first byte of valid
+//! UTF-8, so a stringified message is never mistaken for an envelope.
+
+use anyhow::{Context, Result, anyhow};
+use messages::{Message, MessageUtilities};
+
+use super::encryption::{self, PeerKey};
+use super::integrity::{self, IntegrityError};
+use super::signature::{self, Authenticity, NodeSigningKey, NodeVerifyingKey, SIGNATURE_LENGTH};
+
+const MAGIC: u8 = 0xFF;
+
+/// Upper bound on the size of a decompressed body, protecting against
+/// payloads that expand to an excessive amount of memory.
+const MAX_DECOMPRESSED_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Level passed to the deflate compressor, favouring size over speed.
+const COMPRESSION_LEVEL: u8 = 9;
+
+/// Length of the big-endian CRC-32 checksum preceding a checksummed body.
+/// It covers the magic and flags bytes as well as the body.
+const CHECKSUM_LENGTH: usize = 4;
+
+/// Flags describing how the body of an envelope was produced.
+mod flags {
+    pub const BINARY: u8 = 0b0000_0001;
+    pub const COMPRESSED: u8 = 0b0000_0010;
+    pub const CHECKSUM: u8 = 0b0000_0100;
+    pub const ENCRYPTED: u8 = 0b0000_1000;
+    pub const SIGNED: u8 = 0b0001_0000;
+}
+
+/// How a `Message` is serialized before fragmentation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageEncoding {
+    /// The stringified message, understood by every node.
+    #[default]
+    Text,
+    /// Compact binary serialization. Only nodes using this crate with the
+    /// `binary-encoding` feature understand it.
+    #[cfg(feature = "binary-encoding")]
+    Binary,
+}
+
+/// Options used when serializing a message for a destination.
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions {
+    pub encoding: MessageEncoding,
+    /// Serialized messages of at least this many bytes are deflate
+    /// compressed. `None` disables compression.
+    pub compression_threshold: Option<usize>,
+    /// Whether a checksum over the body is sent along, so that corrupted or
+    /// mixed up fragments are detected on reassembly.
+    pub checksum: bool,
+    /// Key shared with the destination. If set, the body is encrypted.
+    pub encryption_key: Option<PeerKey>,
+    /// Key of this client. If set, the serialized message is signed.
+    pub signing_key: Option<NodeSigningKey>,
+}
+
+/// Options used when parsing a message from a sender.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Key shared with the sender, needed for encrypted messages.
+    pub decryption_key: Option<PeerKey>,
+    /// Key registered for the sender, used to verify signed messages.
+    pub verifying_key: Option<NodeVerifyingKey>,
+}
+
+/// Serializes a message into a payload according to `options`.
+///
+/// # Errors
+/// Returns an error if the message cannot be serialized.
+pub fn encode_message(message: &Message, options: &EncodeOptions) -> Result<Vec<u8>> {
+    let (mut body, mut flags) = match options.encoding {
+        MessageEncoding::Text => (message.stringify().as_bytes().to_vec(), 0),
+        #[cfg(feature = "binary-encoding")]
+        MessageEncoding::Binary => (
+            bincode::serialize(message).with_context(|| "Failed to serialize message!")?,
+            flags::BINARY,
+        ),
+    };
+
+    if let Some(key) = &options.signing_key {
+        let mut signed = Vec::with_capacity(SIGNATURE_LENGTH + body.len());
+        signed.extend_from_slice(&signature::sign(key, &body));
+        signed.extend_from_slice(&body);
+        body = signed;
+        flags |= flags::SIGNED;
+    }
+
+    if options
+        .compression_threshold
+        .is_some_and(|threshold| body.len() >= threshold)
+    {
+        let compressed = miniz_oxide::deflate::compress_to_vec(&body, COMPRESSION_LEVEL);
+        // Small or already dense bodies may grow when compressed.
+        if compressed.len() < body.len() {
+            body = compressed;
+            flags |= flags::COMPRESSED;
+        }
+    }
+
+    if let Some(key) = &options.encryption_key {
+        body = encryption::encrypt(key, &body)?;
+        flags |= flags::ENCRYPTED;
+    }
+
+    if options.checksum {
+        flags |= flags::CHECKSUM;
+    }
+
+    if flags == 0 {
+        return Ok(body);
+    }
+    let header = [MAGIC, flags];
+    let mut payload = Vec::with_capacity(header.len() + CHECKSUM_LENGTH + body.len());
+    payload.extend_from_slice(&header);
+    if options.checksum {
+        let checksum = integrity::checksum_parts(&[&header, &body]);
+        payload.extend_from_slice(&checksum.to_be_bytes());
+    }
+    payload.extend_from_slice(&body);
+    Ok(payload)
+}
+
+/// Parses a payload produced by `encode_message` back into a message.
+///
+/// # Errors
+/// Returns an error if the payload does not contain a valid message. If the
+/// payload was corrupted on its way the error is an `IntegrityError`.
+pub fn decode_message(payload: &[u8], options: &DecodeOptions) -> Result<Message> {
+    decode_authenticated_message(payload, options).map(|(message, _)| message)
+}
+
+/// Parses a payload like `decode_message` and checks its signature against
+/// `options.verifying_key`.
+///
+/// # Errors
+/// Returns an error if the payload does not contain a valid message. If the
+/// payload was corrupted on its way the error is an `IntegrityError`: the
+/// envelope fails its checksum, or it cannot be decoded without a checksum
+/// vouching for it, or the payload is neither an envelope nor text.
+pub fn decode_authenticated_message(
+    payload: &[u8],
+    options: &DecodeOptions,
+) -> Result<(Message, Authenticity)> {
+    let Some((&MAGIC, envelope)) = payload.split_first() else {
+        if std::str::from_utf8(payload).is_err() {
+            return Err(IntegrityError::Malformed(
+                "Payload is neither a message envelope nor text!".to_string(),
+            )
+            .into());
+        }
+        return Ok((parse_text(payload)?, Authenticity::Unsigned));
+    };
+    let Some((&flags, body)) = envelope.split_first() else {
+        return Err(
+            IntegrityError::Malformed("Message envelope is missing flags!".to_string()).into(),
+        );
+    };
+
+    if flags & flags::CHECKSUM == 0 {
+        return decode_envelope_body(flags, body, options)
+            .map_err(|e| IntegrityError::Malformed(e.to_string()).into());
+    }
+    if body.len() < CHECKSUM_LENGTH {
+        return Err(IntegrityError::Malformed(
+            "Message envelope is missing its checksum!".to_string(),
+        )
+        .into());
+    }
+    let (checksum, body) = body.split_at(CHECKSUM_LENGTH);
+    let checksum = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    integrity::verify_parts(&[&[MAGIC, flags], body], checksum)?;
+    // The envelope arrived as it was sent, so other errors are not caused
+    // by corruption and a retransmission would not help.
+    decode_envelope_body(flags, body, options)
+}
+
+/// Decrypts, decompresses, verifies and parses the body of an envelope.
+fn decode_envelope_body(
+    flags: u8,
+    body: &[u8],
+    options: &DecodeOptions,
+) -> Result<(Message, Authenticity)> {
+    let decrypted;
+    let body = if flags & flags::ENCRYPTED == 0 {
+        body
+    } else {
+        let key = options.decryption_key.as_ref().with_context(
+            || "Received an encrypted message but no key is shared with the sender!",
+        )?;
+        decrypted = encryption::decrypt(key, body)?;
+        &decrypted
+    };
+
+    let decompressed;
+    let body = if flags & flags::COMPRESSED == 0 {
+        body
+    } else {
+        decompressed =
+            miniz_oxide::inflate::decompress_to_vec_with_limit(body, MAX_DECOMPRESSED_LENGTH)
+                .map_err(|e| anyhow!("Failed to decompress message: {e}"))?;
+        &decompressed
+    };
+
+    let (body, authenticity) = if flags & flags::SIGNED == 0 {
+        (body, Authenticity::Unsigned)
+    } else {
+        if body.len() < SIGNATURE_LENGTH {
+            return Err(anyhow!("Message envelope is missing its signature!"));
+        }
+        let (signature, body) = body.split_at(SIGNATURE_LENGTH);
+        let mut signature_bytes = [0; SIGNATURE_LENGTH];
+        signature_bytes.copy_from_slice(signature);
+        let authenticity = match &options.verifying_key {
+            None => Authenticity::UnknownSigner,
+            Some(key) if signature::verify(key, body, &signature_bytes) => Authenticity::Verified,
+            Some(_) => Authenticity::Forged,
+        };
+        (body, authenticity)
+    };
+
+    let message = if flags & flags::BINARY == 0 {
+        parse_text(body)?
+    } else {
+        parse_binary(body)?
+    };
+    Ok((message, authenticity))
+}
+
+fn parse_text(body: &[u8]) -> Result<Message> {
+    let message = String::from_utf8(body.to_vec())?;
+    <messages::Message as MessageUtilities>::from_string(message).map_err(|e| anyhow!(e))
+}
+
+#[cfg(feature = "binary-encoding")]
+fn parse_binary(body: &[u8]) -> Result<Message> {
+    bincode::deserialize(body).with_context(|| "Failed to deserialize binary message!")
+}
+
+#[cfg(not(feature = "binary-encoding"))]
+fn parse_binary(_body: &[u8]) -> Result<Message> {
+    Err(anyhow!(
+        "Received a binary encoded message but the binary-encoding feature is disabled!"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use messages::{MessageType, RequestType, TextRequest};
+
+    fn make_test_message() -> Message {
+        Message {
+            source: 1,
+            destination: 2,
+            session_id: 42,
+            content: MessageType::Request(RequestType::TextRequest(TextRequest::Text(
+                "Hello".repeat(50),
+            ))),
+        }
+    }
+
+    #[test]
+    fn test_text_encoding_has_no_envelope() {
+        let message = make_test_message();
+        let payload = encode_message(&message, &EncodeOptions::default()).unwrap();
+        assert_eq!(payload, message.stringify().as_bytes());
+        assert_eq!(
+            decode_message(&payload, &DecodeOptions::default()).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_large_message_is_compressed() {
+        let message = make_test_message();
+        let options = EncodeOptions {
+            compression_threshold: Some(64),
+            ..Default::default()
+        };
+        let payload = encode_message(&message, &options).unwrap();
+        assert_eq!(payload[..2], [MAGIC, flags::COMPRESSED]);
+        assert!(payload.len() < message.stringify().len());
+        assert_eq!(
+            decode_message(&payload, &DecodeOptions::default()).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_message_below_threshold_is_not_compressed() {
+        let message = make_test_message();
+        let options = EncodeOptions {
+            compression_threshold: Some(usize::MAX),
+            ..Default::default()
+        };
+        let payload = encode_message(&message, &options).unwrap();
+        assert_eq!(payload, message.stringify().as_bytes());
+    }
+
+    #[test]
+    fn test_checksum_round_trip() {
+        let message = make_test_message();
+        let options = EncodeOptions {
+            compression_threshold: Some(0),
+            checksum: true,
+            ..Default::default()
+        };
+        let payload = encode_message(&message, &options).unwrap();
+        assert_eq!(payload[..2], [MAGIC, flags::COMPRESSED | flags::CHECKSUM]);
+        assert_eq!(
+            decode_message(&payload, &DecodeOptions::default()).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_corrupted_payload_fails_integrity_check() {
+        let message = make_test_message();
+        let options = EncodeOptions {
+            checksum: true,
+            ..Default::default()
+        };
+        let mut payload = encode_message(&message, &options).unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 0x01;
+
+        let error = decode_message(&payload, &DecodeOptions::default()).unwrap_err();
+        assert!(error.downcast_ref::<integrity::IntegrityError>().is_some());
+    }
+
+    #[test]
+    fn test_corrupted_header_fails_integrity_check() {
+        let message = make_test_message();
+        let options = EncodeOptions {
+            compression_threshold: Some(0),
+            checksum: true,
+            ..Default::default()
+        };
+        let payload = encode_message(&message, &options).unwrap();
+        let is_integrity_error = |payload: &[u8]| {
+            decode_message(payload, &DecodeOptions::default())
+                .unwrap_err()
+                .downcast_ref::<IntegrityError>()
+                .is_some()
+        };
+
+        // Flipped flags are covered by the checksum.
+        let mut flipped = payload.clone();
+        flipped[1] ^= flags::COMPRESSED;
+        assert!(is_integrity_error(&flipped));
+
+        // Without the checksum flag nothing vouches for the envelope.
+        let mut flipped = payload.clone();
+        flipped[1] &= !flags::CHECKSUM;
+        assert!(is_integrity_error(&flipped));
+
+        // Without the magic byte the payload is not valid text either.
+        let mut flipped = payload;
+        flipped[0] = 0xFE;
+        assert!(is_integrity_error(&flipped));
+    }
+
+    #[test]
+    fn test_encryption_round_trip() {
+        let message = make_test_message();
+        let key = PeerKey::generate();
+        let options = EncodeOptions {
+            compression_threshold: Some(0),
+            checksum: true,
+            encryption_key: Some(key.clone()),
+            ..Default::default()
+        };
+        let payload = encode_message(&message, &options).unwrap();
+        assert_eq!(
+            payload[..2],
+            [
+                MAGIC,
+                flags::COMPRESSED | flags::CHECKSUM | flags::ENCRYPTED
+            ]
+        );
+
+        assert!(decode_message(&payload, &DecodeOptions::default()).is_err());
+        let wrong_key = DecodeOptions {
+            decryption_key: Some(PeerKey::generate()),
+            ..Default::default()
+        };
+        assert!(decode_message(&payload, &wrong_key).is_err());
+        let options = DecodeOptions {
+            decryption_key: Some(key),
+            ..Default::default()
+        };
+        assert_eq!(decode_message(&payload, &options).unwrap(), message);
+    }
+
+    #[test]
+    fn test_signature_verification() {
+        let message = make_test_message();
+        let key = NodeSigningKey::generate();
+        let options = EncodeOptions {
+            compression_threshold: Some(0),
+            signing_key: Some(key.clone()),
+            ..Default::default()
+        };
+        let payload = encode_message(&message, &options).unwrap();
+        assert_eq!(payload[..2], [MAGIC, flags::COMPRESSED | flags::SIGNED]);
+
+        let decode = |verifying_key| {
+            let options = DecodeOptions {
+                verifying_key,
+                ..Default::default()
+            };
+            decode_authenticated_message(&payload, &options).unwrap()
+        };
+        assert_eq!(
+            decode(Some(key.verifying_key())),
+            (message.clone(), Authenticity::Verified)
+        );
+        assert_eq!(
+            decode(Some(NodeSigningKey::generate().verifying_key())),
+            (message.clone(), Authenticity::Forged)
+        );
+        assert_eq!(decode(None), (message, Authenticity::UnknownSigner));
+    }
+
+    #[test]
+    fn test_unsigned_message_authenticity() {
+        let message = make_test_message();
+        let payload = encode_message(&message, &EncodeOptions::default()).unwrap();
+        let options = DecodeOptions {
+            verifying_key: Some(NodeSigningKey::generate().verifying_key()),
+            ..Default::default()
+        };
+        let (_, authenticity) = decode_authenticated_message(&payload, &options).unwrap();
+        assert_eq!(authenticity, Authenticity::Unsigned);
+    }
+
+    #[test]
+    fn test_unknown_envelope_is_rejected() {
+        assert!(decode_message(&[MAGIC], &DecodeOptions::default()).is_err());
+    }
+
+    #[cfg(feature = "binary-encoding")]
+    #[test]
+    fn test_binary_encoding_round_trip() {
+        let message = make_test_message();
+        let options = EncodeOptions {
+            encoding: MessageEncoding::Binary,
+            ..Default::default()
+        };
+        let payload = encode_message(&message, &options).unwrap();
+        assert_eq!(payload[..2], [MAGIC, flags::BINARY]);
+        assert!(payload.len() < message.stringify().len());
+        assert_eq!(
+            decode_message(&payload, &DecodeOptions::default()).unwrap(),
+            message
+        );
+    }
+
+    #[cfg(feature = "binary-encoding")]
+    #[test]
+    fn test_binary_encoding_with_compression() {
+        let message = make_test_message();
+        let options = EncodeOptions {
+            encoding: MessageEncoding::Binary,
+            compression_threshold: Some(0),
+            ..Default::default()
+        };
+        let payload = encode_message(&message, &options).unwrap();
+        assert_eq!(payload[..2], [MAGIC, flags::BINARY | flags::COMPRESSED]);
+        assert_eq!(
+            decode_message(&payload, &DecodeOptions::default()).unwrap(),
+            message
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! End-to-end integrity checks of payloads.
+
+use std::fmt;
+
+/// A payload arrived differently than it was sent, e.g. because a drone
+/// corrupted a fragment or fragments of different sessions were mixed.
+///
+/// The router recognizes this error and asks the sender to retransmit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityError {
+    /// The payload did not match the checksum it was sent with.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The payload could not be decoded and nothing vouches that it arrived
+    /// intact, e.g. because the header of its envelope was corrupted.
+    Malformed(String),
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Integrity check failed: expected checksum {expected:08x} but payload has checksum {actual:08x}!"
+            ),
+            IntegrityError::Malformed(reason) => {
+                write!(f, "Integrity check failed: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// Returns the CRC-32 checksum of `data`.
+pub fn checksum(data: &[u8]) -> u32 {
+    checksum_parts(&[data])
+}
+
+/// Returns the CRC-32 checksum of the concatenation of `parts`.
+pub fn checksum_parts(parts: &[&[u8]]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+/// Checks that `data` has the `expected` checksum.
+///
+/// # Errors
+/// Returns an `IntegrityError` if the checksums differ.
+pub fn verify(data: &[u8], expected: u32) -> Result<(), IntegrityError> {
+    verify_parts(&[data], expected)
+}
+
+/// Checks that the concatenation of `parts` has the `expected` checksum.
+///
+/// # Errors
+/// Returns an `IntegrityError` if the checksums differ.
+pub fn verify_parts(parts: &[&[u8]], expected: u32) -> Result<(), IntegrityError> {
+    let actual = checksum_parts(parts);
+    if actual != expected {
+        return Err(IntegrityError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let data = b"payload";
+        assert_eq!(verify(data, checksum(data)), Ok(()));
+
+        let error = verify(b"paylaod", checksum(data)).unwrap_err();
+        assert_eq!(
+            error,
+            IntegrityError::ChecksumMismatch {
+                expected: checksum(data),
+                actual: checksum(b"paylaod"),
+            }
+        );
+        assert_eq!(
+            checksum_parts(&[b"pay".as_slice(), b"load"]),
+            checksum(data)
+        );
+    }
+}
@@ -1 +1,6 @@
+pub mod codec;
+pub mod encryption;
+pub mod envelope;
+pub mod integrity;
+pub mod signature;
 pub mod utils;
@@ -0,0 +1,81 @@
+//! Ed25519 signatures authenticating the sender of a message.
+
+use std::fmt;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// The secret key this client signs its messages with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct NodeSigningKey(pub [u8; 32]);
+
+impl NodeSigningKey {
+    /// Generates a random key.
+    pub fn generate() -> Self {
+        NodeSigningKey(rand::random())
+    }
+
+    /// Returns the public key other nodes verify this client's messages with.
+    pub fn verifying_key(&self) -> NodeVerifyingKey {
+        NodeVerifyingKey(SigningKey::from_bytes(&self.0).verifying_key().to_bytes())
+    }
+}
+
+impl fmt::Debug for NodeSigningKey {
+    /// Keys are never written to logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NodeSigningKey(..)")
+    }
+}
+
+/// The public key messages of a node are verified with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NodeVerifyingKey(pub [u8; 32]);
+
+/// Outcome of checking the signature of a received message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Authenticity {
+    /// The signature matches the key registered for the sender.
+    Verified,
+    /// The message was not signed.
+    Unsigned,
+    /// The message was signed but no key is registered for the sender.
+    UnknownSigner,
+    /// The signature does not match the key registered for the sender.
+    Forged,
+}
+
+/// Signs `data` with `key`.
+pub fn sign(key: &NodeSigningKey, data: &[u8]) -> [u8; SIGNATURE_LENGTH] {
+    SigningKey::from_bytes(&key.0).sign(data).to_bytes()
+}
+
+/// Checks that `signature` was made over `data` with the secret counterpart
+/// of `key`.
+pub fn verify(key: &NodeVerifyingKey, data: &[u8], signature: &[u8; SIGNATURE_LENGTH]) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(&key.0) else {
+        return false;
+    };
+    key.verify(data, &Signature::from_bytes(signature)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = NodeSigningKey::generate();
+        let signature = sign(&key, b"message");
+
+        assert!(verify(&key.verifying_key(), b"message", &signature));
+        assert!(!verify(&key.verifying_key(), b"massage", &signature));
+        assert!(!verify(
+            &NodeSigningKey::generate().verifying_key(),
+            b"message",
+            &signature
+        ));
+    }
+}
@@ -1,52 +1,81 @@
 #![allow(dead_code)]
-use anyhow::{Result, anyhow};
-use assembler::Assembler;
-use assembler::naive_assembler::NaiveAssembler;
-use messages::{Message, MessageUtilities};
+use anyhow::Result;
+use messages::Message;
 use wg_2024::{
     network::SourceRoutingHeader,
     packet::{FloodRequest, NodeType, Packet, PacketType},
 };
 
+use super::codec::FragmentCodec;
+use super::envelope::{self, DecodeOptions, EncodeOptions};
+use super::signature::Authenticity;
+
 /// Converts a `Message` into a vector of `Packet` fragments suitable for sending.
 ///
-/// The message is first stringified, then disassembled into fragments by the
-/// `NaiveAssembler`. Each fragment is wrapped in a `Packet` with the given
+/// The message is first serialized according to `options`, then disassembled
+/// into fragments by the given `codec`. Each fragment is wrapped in a `Packet` with the given
 /// `routing_header` and message `session_id`.
 ///
 /// # Arguments
 ///
 /// * `message` - The message to convert into packets.
 /// * `routing_header` - The source routing header to attach to each packet.
+/// * `codec` - The codec splitting the serialized message into fragments.
+/// * `options` - How the message is serialized for its destination.
 ///
 /// # Returns
 ///
-/// A vector of packets, each containing a fragment of the original message.
-pub fn message_to_packets(message: &Message, routing_header: &SourceRoutingHeader) -> Vec<Packet> {
-    let message_as_string = message.stringify();
-    let fragments = NaiveAssembler::disassemble(message_as_string.as_bytes());
+/// A vector of packets, each containing a fragment of the original message,
+/// or an error if the message cannot be serialized.
+pub fn message_to_packets(
+    message: &Message,
+    routing_header: &SourceRoutingHeader,
+    codec: &dyn FragmentCodec,
+    options: &EncodeOptions,
+) -> Result<Vec<Packet>> {
+    let payload = envelope::encode_message(message, options)?;
+    let fragments = codec.encode(&payload);
 
-    fragments
+    Ok(fragments
         .iter()
         .map(|fragment| {
             Packet::new_fragment(routing_header.clone(), message.session_id, fragment.clone())
         })
-        .collect()
+        .collect())
 }
 
 /// Reassembles a `Message` from a slice of `Packet`s containing message fragments.
 ///
 /// Extracts all message fragments from the packets, reassembles them using
-/// `NaiveAssembler`, and parses the resulting UTF-8 string into a `Message`.
+/// the given `codec`, and parses the resulting payload into a `Message`.
 ///
 /// # Arguments
 ///
 /// * `packets` - Slice of packets potentially containing message fragments.
+/// * `codec` - The codec the fragments were created with.
+/// * `options` - How the message from its sender is parsed.
 ///
 /// # Returns
 ///
 /// Returns `Ok(Message)` if reassembly and parsing succeed, otherwise returns an error.
-pub fn packets_to_message(packets: &[Packet]) -> Result<Message> {
+pub fn packets_to_message(
+    packets: &[Packet],
+    codec: &dyn FragmentCodec,
+    options: &DecodeOptions,
+) -> Result<Message> {
+    packets_to_authenticated_message(packets, codec, options).map(|(message, _)| message)
+}
+
+/// Reassembles a `Message` like `packets_to_message` and reports whether its
+/// signature matches `options.verifying_key`.
+///
+/// # Errors
+/// Returns an error if reassembly or parsing fails.
+pub fn packets_to_authenticated_message(
+    packets: &[Packet],
+    codec: &dyn FragmentCodec,
+    options: &DecodeOptions,
+) -> Result<(Message, Authenticity)> {
     let fragments: Vec<_> = packets
         .iter()
         .filter_map(|packet| {
@@ -58,11 +87,8 @@ pub fn packets_to_message(packets: &[Packet]) -> Result<Message> {
         })
         .collect();
 
-    let message = NaiveAssembler::reassemble(&fragments);
-    let message = String::from_utf8(message)?;
-    let message =
-        <messages::Message as MessageUtilities>::from_string(message).map_err(|e| anyhow!(e))?;
-    Ok(message)
+    let payload = codec.decode(&fragments)?;
+    envelope::decode_authenticated_message(&payload, options)
 }
 
 /// Constructs a new `Packet` containing a `FloodRequest` with the given session ID and initiator ID.
@@ -91,10 +117,20 @@ pub fn get_new_flood_request_packet(session_id: u64, initiator_id: u8) -> Packet
     }
 }
 
+/// Returns a routing header leading back along `routing_header` to its source.
+///
+/// The hop index of the returned header points to the first hop after this node.
+pub fn get_reversed_route(routing_header: &SourceRoutingHeader) -> SourceRoutingHeader {
+    let mut hops = routing_header.hops.clone();
+    hops.reverse();
+    SourceRoutingHeader::new(hops, 1)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use messages::{Message, MessageType, RequestType};
+    use crate::packet::codec::CodecKind;
+    use messages::{Message, MessageType, RequestType, TextRequest};
     use wg_2024::network::SourceRoutingHeader;
     use wg_2024::packet::{NodeType, PacketType};
 
@@ -116,19 +152,134 @@ mod tests {
         let message = make_test_message();
         let routing_header = SourceRoutingHeader::empty_route();
 
-        let packets = message_to_packets(&message, &routing_header);
-        assert!(!packets.is_empty());
+        for codec in [CodecKind::Naive, CodecKind::Checksum] {
+            let codec = codec.build();
+            let packets = message_to_packets(
+                &message,
+                &routing_header,
+                codec.as_ref(),
+                &EncodeOptions::default(),
+            )
+            .expect("Failed to fragment message");
+            assert!(!packets.is_empty());
 
-        for packet in &packets {
-            match &packet.pack_type {
-                PacketType::MsgFragment(_) => (),
-                _ => panic!("Expected MsgFragment packet type"),
+            for packet in &packets {
+                match &packet.pack_type {
+                    PacketType::MsgFragment(_) => (),
+                    _ => panic!("Expected MsgFragment packet type"),
+                }
             }
+
+            let reconstructed =
+                packets_to_message(&packets, codec.as_ref(), &DecodeOptions::default())
+                    .expect("Failed to reconstruct message");
+            assert_eq!(message, reconstructed);
         }
+    }
+    #[cfg(feature = "binary-encoding")]
+    #[test]
+    #[allow(clippy::expect_used)]
+    fn test_binary_message_to_packets_and_back() {
+        use crate::packet::envelope::MessageEncoding;
+
+        let message = make_test_message();
+        let routing_header = SourceRoutingHeader::empty_route();
+        let options = EncodeOptions {
+            encoding: MessageEncoding::Binary,
+            ..Default::default()
+        };
+
+        for codec in [CodecKind::Naive, CodecKind::Checksum] {
+            let codec = codec.build();
+            let packets = message_to_packets(&message, &routing_header, codec.as_ref(), &options)
+                .expect("Failed to fragment message");
+            assert!(!packets.is_empty());
+
+            let reconstructed =
+                packets_to_message(&packets, codec.as_ref(), &DecodeOptions::default())
+                    .expect("Failed to reconstruct message");
+            assert_eq!(message, reconstructed);
+        }
+    }
 
-        let reconstructed = packets_to_message(&packets).expect("Failed to reconstruct message");
+    #[test]
+    #[allow(clippy::expect_used)]
+    fn test_compression_reduces_fragment_count() {
+        let mut message = make_test_message();
+        message.content = MessageType::Request(RequestType::TextRequest(TextRequest::Text(
+            "All work and no play makes Jack a dull boy. ".repeat(40),
+        )));
+        let routing_header = SourceRoutingHeader::empty_route();
+        let codec = CodecKind::Naive.build();
+
+        let plain = message_to_packets(
+            &message,
+            &routing_header,
+            codec.as_ref(),
+            &EncodeOptions::default(),
+        )
+        .expect("Failed to fragment message");
+        let options = EncodeOptions {
+            compression_threshold: Some(256),
+            ..Default::default()
+        };
+        let compressed = message_to_packets(&message, &routing_header, codec.as_ref(), &options)
+            .expect("Failed to fragment message");
+        assert!(compressed.len() < plain.len());
+
+        let reconstructed =
+            packets_to_message(&compressed, codec.as_ref(), &DecodeOptions::default())
+                .expect("Failed to reconstruct message");
+        assert_eq!(message, reconstructed);
+    }
+
+    #[test]
+    #[allow(clippy::expect_used)]
+    fn test_encrypted_fragments_do_not_contain_plaintext() {
+        use crate::packet::encryption::PeerKey;
+
+        let secret = "attack at dawn";
+        let mut message = make_test_message();
+        message.content = MessageType::Request(RequestType::TextRequest(TextRequest::Text(
+            secret.to_string(),
+        )));
+        let routing_header = SourceRoutingHeader::empty_route();
+        let codec = CodecKind::Naive.build();
+        let key = PeerKey::generate();
+        let options = EncodeOptions {
+            encryption_key: Some(key.clone()),
+            ..Default::default()
+        };
+
+        let packets = message_to_packets(&message, &routing_header, codec.as_ref(), &options)
+            .expect("Failed to fragment message");
+        // Everything a drone on the route can observe.
+        let observed: Vec<u8> = packets
+            .iter()
+            .filter_map(|packet| match &packet.pack_type {
+                PacketType::MsgFragment(fragment) => {
+                    Some(fragment.data[..usize::from(fragment.length)].to_vec())
+                }
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert!(
+            !observed
+                .windows(secret.len())
+                .any(|window| window == secret.as_bytes())
+        );
+
+        assert!(packets_to_message(&packets, codec.as_ref(), &DecodeOptions::default()).is_err());
+        let options = DecodeOptions {
+            decryption_key: Some(key),
+            ..Default::default()
+        };
+        let reconstructed = packets_to_message(&packets, codec.as_ref(), &options)
+            .expect("Failed to reconstruct message");
         assert_eq!(message, reconstructed);
     }
+
     #[test]
     #[allow(clippy::panic)]
     fn test_get_new_flood_request_packet() {
@@ -148,4 +299,12 @@ mod tests {
             _ => panic!("Expected FloodRequest packet type"),
         }
     }
+
+    #[test]
+    fn test_get_reversed_route() {
+        let routing_header = SourceRoutingHeader::new(vec![1, 2, 3, 4], 3);
+        let reversed = get_reversed_route(&routing_header);
+        assert_eq!(reversed.hops, vec![4, 3, 2, 1]);
+        assert_eq!(reversed.hop_index, 1);
+    }
 }
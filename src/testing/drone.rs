@@ -0,0 +1,327 @@
+//! A minimal drone following the protocol of the simulation.
+
+use std::collections::{HashMap, HashSet};
+
+use crossbeam_channel::{Receiver, Sender, select};
+use log::warn;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use wg_2024::controller::DroneCommand;
+use wg_2024::network::{NodeId, SourceRoutingHeader};
+use wg_2024::packet::{FloodRequest, Nack, NackType, NodeType, Packet, PacketType};
+
+use super::send_along_route;
+
+/// An in-process drone forwarding packets along their source route.
+///
+/// Message fragments are dropped with the configured probability and NACKed
+/// as `Dropped`. Routing errors are NACKed like real drones do. Flood
+/// requests are forwarded to every neighbor except the one they came from and
+/// answered once they reach a dead end or a drone that has already seen them.
+pub struct SimulatedDrone {
+    id: NodeId,
+    pdr: f32,
+    packet_recv: Receiver<Packet>,
+    command_recv: Receiver<DroneCommand>,
+    neighbors: HashMap<NodeId, Sender<Packet>>,
+    seen_floods: HashSet<(u64, NodeId)>,
+    rng: StdRng,
+    crashed: bool,
+}
+
+impl SimulatedDrone {
+    pub fn new(
+        id: NodeId,
+        pdr: f32,
+        packet_recv: Receiver<Packet>,
+        command_recv: Receiver<DroneCommand>,
+        neighbors: HashMap<NodeId, Sender<Packet>>,
+    ) -> Self {
+        SimulatedDrone {
+            id,
+            pdr,
+            packet_recv,
+            command_recv,
+            neighbors,
+            seen_floods: HashSet::new(),
+            rng: StdRng::from_os_rng(),
+            crashed: false,
+        }
+    }
+
+    /// Makes the dropped fragments reproducible for the same sequence of packets.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Handles packets and commands until the drone crashes or its channels
+    /// are disconnected.
+    pub fn run(&mut self) {
+        while !self.crashed {
+            select! {
+                recv(self.command_recv) -> command => match command {
+                    Ok(command) => self.handle_command(command),
+                    Err(_) => return,
+                },
+                recv(self.packet_recv) -> packet => match packet {
+                    Ok(packet) => self.handle_packet(packet),
+                    Err(_) => return,
+                },
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: DroneCommand) {
+        match command {
+            DroneCommand::AddSender(node_id, sender) => {
+                self.neighbors.insert(node_id, sender);
+            }
+            DroneCommand::RemoveSender(node_id) => {
+                self.neighbors.remove(&node_id);
+            }
+            DroneCommand::SetPacketDropRate(pdr) => self.pdr = pdr,
+            DroneCommand::Crash => self.crashed = true,
+        }
+    }
+
+    fn handle_packet(&mut self, packet: Packet) {
+        if let PacketType::FloodRequest(flood_request) = &packet.pack_type {
+            let flood_request = flood_request.clone();
+            self.handle_flood_request(packet.session_id, flood_request);
+            return;
+        }
+
+        let hop_index = packet.routing_header.hop_index;
+        if packet.routing_header.hops.get(hop_index) != Some(&self.id) {
+            self.send_nack(&packet, NackType::UnexpectedRecipient(self.id));
+            return;
+        }
+        let Some(&next_hop) = packet.routing_header.hops.get(hop_index + 1) else {
+            self.send_nack(&packet, NackType::DestinationIsDrone);
+            return;
+        };
+        if !self.neighbors.contains_key(&next_hop) {
+            self.send_nack(&packet, NackType::ErrorInRouting(next_hop));
+            return;
+        }
+        if matches!(packet.pack_type, PacketType::MsgFragment(_))
+            && self.rng.random::<f32>() < self.pdr
+        {
+            self.send_nack(&packet, NackType::Dropped);
+            return;
+        }
+
+        let mut packet = packet;
+        packet.routing_header.hop_index += 1;
+        self.send_to(next_hop, packet);
+    }
+
+    fn handle_flood_request(&mut self, session_id: u64, mut flood_request: FloodRequest) {
+        let previous_hop = flood_request.path_trace.last().map(|(node_id, _)| *node_id);
+        flood_request.path_trace.push((self.id, NodeType::Drone));
+
+        let first_visit = self
+            .seen_floods
+            .insert((flood_request.flood_id, flood_request.initiator_id));
+        let next_hops: Vec<NodeId> = self
+            .neighbors
+            .keys()
+            .copied()
+            .filter(|node_id| Some(*node_id) != previous_hop)
+            .collect();
+
+        if !first_visit || next_hops.is_empty() {
+            let mut response = flood_request.generate_response(session_id);
+            response.routing_header.hop_index = 1;
+            self.forward_along_route(response);
+            return;
+        }
+        let packet = Packet {
+            routing_header: SourceRoutingHeader::empty_route(),
+            session_id,
+            pack_type: PacketType::FloodRequest(flood_request),
+        };
+        for next_hop in next_hops {
+            self.send_to(next_hop, packet.clone());
+        }
+    }
+
+    /// NACKs a packet back to its source. Only message fragments are NACKed,
+    /// other packets would be delivered by the simulation controller and are
+    /// discarded here.
+    fn send_nack(&self, packet: &Packet, nack_type: NackType) {
+        let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+            warn!(
+                "Drone {} discarded an undeliverable packet: {packet:?}",
+                self.id
+            );
+            return;
+        };
+        let hop_index = packet.routing_header.hop_index;
+        let mut hops: Vec<NodeId> = packet
+            .routing_header
+            .hops
+            .iter()
+            .take(hop_index)
+            .copied()
+            .collect();
+        hops.push(self.id);
+        hops.reverse();
+
+        let nack = Packet {
+            routing_header: SourceRoutingHeader::new(hops, 1),
+            session_id: packet.session_id,
+            pack_type: PacketType::Nack(Nack {
+                fragment_index: fragment.fragment_index,
+                nack_type,
+            }),
+        };
+        self.forward_along_route(nack);
+    }
+
+    fn forward_along_route(&self, packet: Packet) {
+        send_along_route(self.id, &self.neighbors, packet);
+    }
+
+    fn send_to(&self, node_id: NodeId, packet: Packet) {
+        let sent = self
+            .neighbors
+            .get(&node_id)
+            .is_some_and(|channel| channel.send(packet).is_ok());
+        if !sent {
+            warn!("Drone {} failed to send a packet to {node_id}", self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::panic)]
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use wg_2024::packet::Fragment;
+
+    const DRONE: NodeId = 2;
+
+    struct Fixture {
+        drone: SimulatedDrone,
+        client: Receiver<Packet>,
+        server: Receiver<Packet>,
+    }
+
+    /// Drone 2 connected to client 1 and server 3.
+    fn fixture(pdr: f32) -> Fixture {
+        let (client_send, client) = unbounded();
+        let (server_send, server) = unbounded();
+        let (_, packet_recv) = unbounded();
+        let (_, command_recv) = unbounded();
+        let neighbors = HashMap::from([(1, client_send), (3, server_send)]);
+        Fixture {
+            drone: SimulatedDrone::new(DRONE, pdr, packet_recv, command_recv, neighbors),
+            client,
+            server,
+        }
+    }
+
+    fn fragment(hops: Vec<NodeId>) -> Packet {
+        Packet::new_fragment(
+            SourceRoutingHeader::new(hops, 1),
+            7,
+            Fragment {
+                fragment_index: 0,
+                total_n_fragments: 1,
+                length: 5,
+                data: [7; 128],
+            },
+        )
+    }
+
+    fn expect_nack(packet: Packet) -> NackType {
+        assert_eq!(packet.routing_header.hops, vec![DRONE, 1]);
+        assert_eq!(packet.routing_header.hop_index, 1);
+        match packet.pack_type {
+            PacketType::Nack(nack) => nack.nack_type,
+            other => panic!("Expected NACK, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_fragment_is_forwarded() {
+        let mut fixture = fixture(0.0);
+        fixture.drone.handle_packet(fragment(vec![1, DRONE, 3]));
+
+        let forwarded = fixture.server.try_recv().unwrap();
+        assert_eq!(forwarded.routing_header.hop_index, 2);
+        assert!(fixture.client.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_fragment_is_dropped() {
+        let mut fixture = fixture(1.0);
+        fixture.drone.handle_packet(fragment(vec![1, DRONE, 3]));
+
+        assert!(fixture.server.try_recv().is_err());
+        let nack = expect_nack(fixture.client.try_recv().unwrap());
+        assert_eq!(nack, NackType::Dropped);
+    }
+
+    #[test]
+    fn test_seeded_drones_drop_the_same_fragments() {
+        let drop_pattern = || {
+            let mut fixture = fixture(0.5);
+            fixture.drone = fixture.drone.with_seed(42);
+            (0..32)
+                .map(|_| {
+                    fixture.drone.handle_packet(fragment(vec![1, DRONE, 3]));
+                    fixture.client.try_recv().is_ok()
+                })
+                .collect::<Vec<bool>>()
+        };
+
+        let dropped = drop_pattern();
+        assert_eq!(dropped, drop_pattern());
+        assert!(dropped.contains(&true) && dropped.contains(&false));
+    }
+
+    #[test]
+    fn test_routing_errors_are_nacked() {
+        let mut fixture = fixture(0.0);
+        fixture.drone.handle_packet(fragment(vec![1, DRONE, 9]));
+        let nack = expect_nack(fixture.client.try_recv().unwrap());
+        assert_eq!(nack, NackType::ErrorInRouting(9));
+
+        fixture.drone.handle_packet(fragment(vec![1, DRONE]));
+        let nack = expect_nack(fixture.client.try_recv().unwrap());
+        assert_eq!(nack, NackType::DestinationIsDrone);
+    }
+
+    #[test]
+    fn test_flood_request_is_forwarded_then_answered() {
+        let mut fixture = fixture(0.0);
+        let flood_request = FloodRequest {
+            flood_id: 1,
+            initiator_id: 1,
+            path_trace: vec![(1, NodeType::Client)],
+        };
+
+        fixture.drone.handle_flood_request(5, flood_request.clone());
+        let forwarded = fixture.server.try_recv().unwrap();
+        let PacketType::FloodRequest(forwarded) = forwarded.pack_type else {
+            panic!("Expected flood request");
+        };
+        assert_eq!(
+            forwarded.path_trace,
+            vec![(1, NodeType::Client), (DRONE, NodeType::Drone)]
+        );
+        assert!(fixture.client.try_recv().is_err());
+
+        // The same flood arriving again is answered instead of forwarded.
+        fixture.drone.handle_flood_request(5, flood_request);
+        let response = fixture.client.try_recv().unwrap();
+        assert!(matches!(response.pack_type, PacketType::FloodResponse(_)));
+        assert_eq!(response.routing_header.hops, vec![DRONE, 1]);
+        assert!(fixture.server.try_recv().is_err());
+    }
+}
@@ -0,0 +1,35 @@
+//! Support for testing clients against an in-process network.
+//!
+//! Available with the `testing` feature. `SimulatedNetwork` connects
+//! `SimulatedDrone`s, `MockServer`s and `Service` instances with crossbeam
+//! channels according to a `Topology` and runs each node on its own thread.
+
+pub mod drone;
+pub mod network;
+pub mod server;
+
+use std::collections::HashMap;
+
+use crossbeam_channel::Sender;
+use log::warn;
+use wg_2024::network::NodeId;
+use wg_2024::packet::Packet;
+
+pub use drone::SimulatedDrone;
+pub use network::{ClientHandle, SimulatedNetwork, Topology};
+pub use server::{MockServer, MockServerBehavior, Responder, default_response};
+
+/// Sends `packet` to the neighbor at the current hop of its routing header.
+fn send_along_route(node_id: NodeId, neighbors: &HashMap<NodeId, Sender<Packet>>, packet: Packet) {
+    let next_hop = packet
+        .routing_header
+        .hops
+        .get(packet.routing_header.hop_index)
+        .copied();
+    let sent = next_hop
+        .and_then(|next_hop| neighbors.get(&next_hop))
+        .is_some_and(|channel| channel.send(packet).is_ok());
+    if !sent {
+        warn!("Node {node_id} failed to send a packet to {next_hop:?}");
+    }
+}
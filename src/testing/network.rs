@@ -0,0 +1,676 @@
+//! Wiring of simulated nodes into a running network.
+
+use std::collections::HashMap;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use messages::Message;
+use messages::node_event::NodeEvent;
+use wg_2024::controller::DroneCommand;
+use wg_2024::network::NodeId;
+use wg_2024::packet::{NodeType, Packet};
+
+use super::{MockServer, MockServerBehavior, SimulatedDrone};
+use crate::backend::{
+    ApiResponse, Command, ListOfDiscoveredEdgeNodes, Service, ServiceConfig, ServiceEvent,
+    UnreadMessagesFromServer,
+};
+
+/// Nodes of a simulated network and the links between them.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    drones: Vec<(NodeId, f32)>,
+    clients: Vec<NodeId>,
+    servers: Vec<(NodeId, MockServerBehavior)>,
+    links: Vec<(NodeId, NodeId)>,
+    seed: Option<u64>,
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Topology::default()
+    }
+
+    /// Adds a drone dropping message fragments with probability `pdr`.
+    #[must_use]
+    pub fn drone(mut self, node_id: NodeId, pdr: f32) -> Self {
+        self.drones.push((node_id, pdr));
+        self
+    }
+
+    /// Adds a client running a `Service`.
+    #[must_use]
+    pub fn client(mut self, node_id: NodeId) -> Self {
+        self.clients.push(node_id);
+        self
+    }
+
+    /// Adds a `MockServer` with the default behavior.
+    #[must_use]
+    pub fn server(self, node_id: NodeId) -> Self {
+        self.scripted_server(node_id, MockServerBehavior::default())
+    }
+
+    /// Adds a `MockServer` answering and misbehaving according to `behavior`.
+    #[must_use]
+    pub fn scripted_server(mut self, node_id: NodeId, behavior: MockServerBehavior) -> Self {
+        self.servers.push((node_id, behavior));
+        self
+    }
+
+    /// Seeds the random choices of drones and clients, unless a client
+    /// configuration sets its own seed.
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Connects two nodes in both directions.
+    #[must_use]
+    pub fn link(mut self, a: NodeId, b: NodeId) -> Self {
+        self.links.push((a, b));
+        self
+    }
+
+    fn neighbors_of(&self, node_id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.links.iter().filter_map(move |&(a, b)| {
+            if a == node_id {
+                Some(b)
+            } else if b == node_id {
+                Some(a)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn node_type(&self, node_id: NodeId) -> Option<NodeType> {
+        if self.drones.iter().any(|(drone, _)| *drone == node_id) {
+            Some(NodeType::Drone)
+        } else if self.clients.contains(&node_id) {
+            Some(NodeType::Client)
+        } else if self.servers.iter().any(|(server, _)| *server == node_id) {
+            Some(NodeType::Server)
+        } else {
+            None
+        }
+    }
+}
+
+/// Channels for driving a client of a `SimulatedNetwork` and observing it.
+pub struct ClientHandle {
+    pub node_id: NodeId,
+    /// Inbound packet channel of the client, e.g. for replaying recorded traffic.
+    pub packets: Sender<Packet>,
+    pub commands: Sender<Command>,
+    pub sc_commands: Sender<DroneCommand>,
+    pub node_events: Receiver<NodeEvent>,
+    pub service_events: Receiver<ServiceEvent>,
+    pub api_responses: Receiver<ApiResponse>,
+    pub edge_nodes: Receiver<ListOfDiscoveredEdgeNodes>,
+    pub unread_messages: Receiver<UnreadMessagesFromServer>,
+}
+
+impl ClientHandle {
+    /// Sends a `Command` to the client.
+    ///
+    /// # Errors
+    /// Returns an error if the client has stopped.
+    pub fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .with_context(|| format!("Client {} has stopped!", self.node_id))
+    }
+
+    /// Waits for a `NodeEvent` matching `predicate`, skipping any other events.
+    ///
+    /// # Errors
+    /// Returns an error if no matching event arrives within `timeout`.
+    pub fn wait_for_event(
+        &self,
+        timeout: Duration,
+        mut predicate: impl FnMut(&NodeEvent) -> bool,
+    ) -> Result<NodeEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.node_events.recv_timeout(remaining) {
+                Ok(event) if predicate(&event) => return Ok(event),
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(anyhow!(
+                        "Client {} did not emit the expected event within {timeout:?}!",
+                        self.node_id
+                    ));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow!("Client {} has stopped!", self.node_id));
+                }
+            }
+        }
+    }
+}
+
+/// A running network of simulated drones, mock servers and clients.
+///
+/// Every node runs on its own thread until the network is dropped, which
+/// crashes all nodes and waits for their threads to finish.
+pub struct SimulatedNetwork {
+    clients: HashMap<NodeId, ClientHandle>,
+    server_messages: HashMap<NodeId, Receiver<Message>>,
+    node_commands: Vec<Sender<DroneCommand>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl SimulatedNetwork {
+    /// Starts the network with the default `ServiceConfig` for every client.
+    ///
+    /// # Errors
+    /// Returns an error if a client rejects its configuration.
+    pub fn start(topology: &Topology) -> Result<Self> {
+        Self::start_with_config(topology, |_| ServiceConfig::default())
+    }
+
+    /// Starts the network with the `ServiceConfig` returned by `config` for
+    /// each client. The API response channel of the configuration is
+    /// replaced by the one of the `ClientHandle`.
+    ///
+    /// # Errors
+    /// Returns an error if the topology links unknown nodes or a client
+    /// rejects its configuration.
+    pub fn start_with_config(
+        topology: &Topology,
+        config: impl Fn(NodeId) -> ServiceConfig,
+    ) -> Result<Self> {
+        for (a, b) in &topology.links {
+            for node_id in [a, b] {
+                topology
+                    .node_type(*node_id)
+                    .with_context(|| format!("Link refers to unknown node {node_id}!"))?;
+            }
+        }
+
+        let mut packet_channels: HashMap<NodeId, (Sender<Packet>, Receiver<Packet>)> =
+            HashMap::new();
+        let node_ids = topology
+            .drones
+            .iter()
+            .map(|(node_id, _)| *node_id)
+            .chain(topology.clients.iter().copied())
+            .chain(topology.servers.iter().map(|(node_id, _)| *node_id));
+        for node_id in node_ids {
+            packet_channels.insert(node_id, unbounded());
+        }
+        let neighbors_of = |node_id: NodeId| -> HashMap<NodeId, Sender<Packet>> {
+            topology
+                .neighbors_of(node_id)
+                .filter_map(|neighbor| {
+                    packet_channels
+                        .get(&neighbor)
+                        .map(|(sender, _)| (neighbor, sender.clone()))
+                })
+                .collect()
+        };
+        let packet_recv_of = |node_id: NodeId| -> Result<Receiver<Packet>> {
+            packet_channels
+                .get(&node_id)
+                .map(|(_, receiver)| receiver.clone())
+                .with_context(|| format!("No packet channel for node {node_id}!"))
+        };
+
+        let mut network = SimulatedNetwork {
+            clients: HashMap::new(),
+            server_messages: HashMap::new(),
+            node_commands: vec![],
+            threads: vec![],
+        };
+
+        for &(node_id, pdr) in &topology.drones {
+            let (command_send, command_recv) = unbounded();
+            let mut drone = SimulatedDrone::new(
+                node_id,
+                pdr,
+                packet_recv_of(node_id)?,
+                command_recv,
+                neighbors_of(node_id),
+            );
+            if let Some(seed) = topology.seed {
+                drone = drone.with_seed(seed.wrapping_add(u64::from(node_id)));
+            }
+            network.node_commands.push(command_send);
+            network.threads.push(thread::spawn(move || drone.run()));
+        }
+
+        for (node_id, behavior) in &topology.servers {
+            let node_id = *node_id;
+            let (command_send, command_recv) = unbounded();
+            let (messages_send, messages_recv) = unbounded();
+            let mut server = MockServer::new(
+                node_id,
+                packet_recv_of(node_id)?,
+                command_recv,
+                neighbors_of(node_id),
+                messages_send,
+            )
+            .with_behavior(behavior.clone());
+            network.node_commands.push(command_send);
+            network.server_messages.insert(node_id, messages_recv);
+            network.threads.push(thread::spawn(move || server.run()));
+        }
+
+        for &node_id in &topology.clients {
+            let (node_event_send, node_events) = unbounded();
+            let (sc_commands, sc_command_recv) = unbounded();
+            let (commands, command_recv) = unbounded();
+            let (service_event_send, service_events) = unbounded();
+            let (api_response_send, api_responses) = unbounded();
+            let (edge_nodes_send, edge_nodes) = unbounded();
+            let (unread_send, unread_messages) = unbounded();
+
+            let mut client_config = config(node_id);
+            if let Some(seed) = topology.seed {
+                client_config.rng_seed = client_config
+                    .rng_seed
+                    .or(Some(seed.wrapping_add(u64::from(node_id))));
+            }
+            client_config.api_response_channel = Some(api_response_send);
+            for neighbor in topology.neighbors_of(node_id) {
+                if let Some(node_type) = topology.node_type(neighbor) {
+                    client_config.neighbor_types.insert(neighbor, node_type);
+                }
+            }
+
+            let mut service = Service::with_config(
+                node_id,
+                node_event_send,
+                sc_command_recv,
+                service_event_send,
+                neighbors_of(node_id),
+                packet_recv_of(node_id)?,
+                command_recv,
+                edge_nodes_send,
+                unread_send,
+                client_config,
+            )
+            .map_err(|e| anyhow!("Failed to start client {node_id}: {e}"))?;
+
+            network.threads.push(thread::spawn(move || service.run()));
+            network.clients.insert(
+                node_id,
+                ClientHandle {
+                    node_id,
+                    packets: packet_channels
+                        .get(&node_id)
+                        .map(|(sender, _)| sender.clone())
+                        .with_context(|| format!("No packet channel for node {node_id}!"))?,
+                    commands,
+                    sc_commands,
+                    node_events,
+                    service_events,
+                    api_responses,
+                    edge_nodes,
+                    unread_messages,
+                },
+            );
+        }
+
+        Ok(network)
+    }
+
+    pub fn client(&self, node_id: NodeId) -> Option<&ClientHandle> {
+        self.clients.get(&node_id)
+    }
+
+    /// Returns the requests received by a server.
+    pub fn server_messages(&self, node_id: NodeId) -> Option<&Receiver<Message>> {
+        self.server_messages.get(&node_id)
+    }
+}
+
+impl Drop for SimulatedNetwork {
+    fn drop(&mut self) {
+        for client in self.clients.values() {
+            let _ = client.sc_commands.send(DroneCommand::Crash);
+        }
+        for node_commands in &self.node_commands {
+            let _ = node_commands.send(DroneCommand::Crash);
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::panic)]
+    use std::collections::HashSet;
+
+    use messages::{MessageType, RequestType, ResponseType, ServerType, TextRequest, TextResponse};
+
+    use wg_2024::packet::PacketType;
+
+    use super::*;
+    use crate::backend::{
+        ControllerCommand, KnownTopology, TopologyNode, TrafficRecord, replay_inbound,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Client 1 connected to server 4 through drones 2 and 3.
+    fn line_topology(pdr: f32) -> Topology {
+        Topology::new()
+            .client(1)
+            .drone(2, pdr)
+            .drone(3, pdr)
+            .server(4)
+            .link(1, 2)
+            .link(2, 3)
+            .link(3, 4)
+    }
+
+    #[test]
+    fn test_flood_discovers_drones() {
+        let network = SimulatedNetwork::start(&line_topology(0.0)).unwrap();
+        let client = network.client(1).unwrap();
+
+        client.send(Command::InitializeFlood).unwrap();
+        client
+            .wait_for_event(TIMEOUT, |event| match event {
+                NodeEvent::KnownNetworkGraph { graph, .. } => [2, 3]
+                    .iter()
+                    .all(|drone| graph.nodes.iter().any(|node| node.node_id == *drone)),
+                _ => false,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_message_to_server_is_acknowledged_and_answered() {
+        let network = SimulatedNetwork::start(&line_topology(0.0)).unwrap();
+        let client = network.client(1).unwrap();
+
+        client.send(Command::InitializeFlood).unwrap();
+        client
+            .wait_for_event(TIMEOUT, |event| match event {
+                NodeEvent::KnownNetworkGraph { graph, .. } => {
+                    graph.nodes.iter().any(|node| node.node_id == 4)
+                }
+                _ => false,
+            })
+            .unwrap();
+
+        let content = MessageType::Request(RequestType::DiscoveryRequest(()));
+        client
+            .send(Command::SendMessage(Message {
+                source: 1,
+                destination: 4,
+                session_id: 0,
+                content: content.clone(),
+            }))
+            .unwrap();
+
+        let (mut delivered, mut response) = (false, None);
+        client
+            .wait_for_event(TIMEOUT, |event| {
+                match event {
+                    NodeEvent::MessageSentSuccessfully(message) => {
+                        delivered |= message.destination == 4
+                    }
+                    NodeEvent::MessageReceived(message) if message.source == 4 => {
+                        response = Some(message.clone())
+                    }
+                    _ => {}
+                }
+                delivered && response.is_some()
+            })
+            .unwrap();
+        let response = response.unwrap();
+        assert_eq!(response.destination, 1);
+        assert_eq!(
+            response.content,
+            MessageType::Response(ResponseType::DiscoveryResponse(ServerType::Text))
+        );
+
+        let received = network
+            .server_messages(4)
+            .unwrap()
+            .recv_timeout(TIMEOUT)
+            .unwrap();
+        assert_eq!(received.source, 1);
+        assert_eq!(received.content, content);
+    }
+
+    #[test]
+    fn test_dropped_fragments_are_resent_until_delivered() {
+        let network = SimulatedNetwork::start(&line_topology(0.5).seed(7)).unwrap();
+        let client = network.client(1).unwrap();
+
+        client.send(Command::InitializeFlood).unwrap();
+        client
+            .wait_for_event(TIMEOUT, |event| match event {
+                NodeEvent::KnownNetworkGraph { graph, .. } => {
+                    graph.nodes.iter().any(|node| node.node_id == 4)
+                }
+                _ => false,
+            })
+            .unwrap();
+
+        // Long enough to span several fragments, each crossing two lossy drones.
+        let text = "ping".repeat(200);
+        client
+            .send(Command::SendMessage(Message {
+                source: 1,
+                destination: 4,
+                session_id: 0,
+                content: MessageType::Request(RequestType::TextRequest(TextRequest::Text(
+                    text.clone(),
+                ))),
+            }))
+            .unwrap();
+
+        let mut sent_fragments = HashSet::new();
+        let (mut resent, mut delivered, mut response) = (false, false, None);
+        client
+            .wait_for_event(TIMEOUT, |event| {
+                match event {
+                    NodeEvent::PacketSent(packet) => {
+                        if let PacketType::MsgFragment(fragment) = &packet.pack_type {
+                            let fragment_id = (packet.session_id, fragment.fragment_index);
+                            resent |= !sent_fragments.insert(fragment_id);
+                        }
+                    }
+                    NodeEvent::MessageSentSuccessfully(message) => {
+                        delivered |= message.destination == 4
+                    }
+                    NodeEvent::MessageReceived(message) if message.source == 4 => {
+                        response = Some(message.clone())
+                    }
+                    _ => {}
+                }
+                delivered && response.is_some()
+            })
+            .unwrap();
+        assert!(resent);
+        assert_eq!(
+            response.unwrap().content,
+            MessageType::Response(ResponseType::TextResponse(TextResponse::Text(text.clone())))
+        );
+
+        let received = network
+            .server_messages(4)
+            .unwrap()
+            .recv_timeout(TIMEOUT)
+            .unwrap();
+        assert_eq!(
+            received.content,
+            MessageType::Request(RequestType::TextRequest(TextRequest::Text(text)))
+        );
+
+        client.send(Command::GetStats).unwrap();
+        let ApiResponse::Stats(stats) = client.api_responses.recv_timeout(TIMEOUT).unwrap() else {
+            panic!("Expected stats");
+        };
+        assert!(stats.nacks_received.dropped > 0);
+        assert!(stats.retransmissions > 0);
+    }
+
+    #[test]
+    fn test_recorded_traffic_replays_into_a_running_client() {
+        let log_path = std::env::temp_dir().join(format!("replay-{}.jsonl", std::process::id()));
+        let recording =
+            SimulatedNetwork::start_with_config(&line_topology(0.0), |_| ServiceConfig {
+                traffic_log_path: Some(log_path.clone()),
+                ..ServiceConfig::default()
+            })
+            .unwrap();
+        let client = recording.client(1).unwrap();
+        client.send(Command::InitializeFlood).unwrap();
+        client
+            .wait_for_event(TIMEOUT, |event| match event {
+                NodeEvent::KnownNetworkGraph { graph, .. } => {
+                    graph.nodes.iter().any(|node| node.node_id == 4)
+                }
+                _ => false,
+            })
+            .unwrap();
+        client
+            .send(Command::SendMessage(Message {
+                source: 1,
+                destination: 4,
+                session_id: 0,
+                content: MessageType::Request(RequestType::TextRequest(TextRequest::Text(
+                    "ping".to_string(),
+                ))),
+            }))
+            .unwrap();
+        client
+            .wait_for_event(
+                TIMEOUT,
+                |event| matches!(event, NodeEvent::MessageReceived(message) if message.source == 4),
+            )
+            .unwrap();
+        drop(recording);
+
+        let records = TrafficRecord::read_log(&log_path).unwrap();
+        std::fs::remove_file(&log_path).unwrap();
+        let network = SimulatedNetwork::start(&line_topology(0.0)).unwrap();
+        let client = network.client(1).unwrap();
+        assert!(replay_inbound(&records, &client.packets).unwrap() > 0);
+
+        // The replayed client learns the route and receives the response
+        // without sending anything itself.
+        let event = client
+            .wait_for_event(
+                TIMEOUT,
+                |event| matches!(event, NodeEvent::MessageReceived(message) if message.source == 4),
+            )
+            .unwrap();
+        let NodeEvent::MessageReceived(response) = event else {
+            panic!("Expected a received message");
+        };
+        assert_eq!(
+            response.content,
+            MessageType::Response(ResponseType::TextResponse(TextResponse::Text(
+                "ping".to_string()
+            )))
+        );
+        assert!(network.server_messages(4).unwrap().try_recv().is_err());
+
+        client.send(Command::GetTopology).unwrap();
+        let ApiResponse::Topology(topology) = client.api_responses.recv_timeout(TIMEOUT).unwrap()
+        else {
+            panic!("Expected the known topology");
+        };
+        assert!(topology.nodes.iter().any(|node| node.node_id == 4));
+        client.send(Command::GetStats).unwrap();
+        let ApiResponse::Stats(stats) = client.api_responses.recv_timeout(TIMEOUT).unwrap() else {
+            panic!("Expected stats");
+        };
+        assert_eq!(stats.messages_received, 1);
+        assert_eq!(stats.messages_sent, 0);
+    }
+
+    #[test]
+    fn test_seeded_topology_is_reported_on_start() {
+        let node = |node_id, node_type| TopologyNode {
+            node_id,
+            node_type,
+            dropped_packets: 0,
+        };
+        let topology = KnownTopology {
+            node_id: 1,
+            nodes: vec![
+                node(1, NodeType::Client),
+                node(2, NodeType::Drone),
+                node(3, NodeType::Drone),
+                node(4, NodeType::Server),
+            ],
+            edges: vec![(1, 2), (2, 3), (3, 4)],
+        };
+        let network = SimulatedNetwork::start_with_config(&line_topology(0.0), |_| ServiceConfig {
+            initial_topology: Some(topology.clone()),
+            ..ServiceConfig::default()
+        })
+        .unwrap();
+
+        let event = network
+            .client(1)
+            .unwrap()
+            .node_events
+            .recv_timeout(TIMEOUT)
+            .unwrap();
+        let NodeEvent::KnownNetworkGraph { source, graph } = event else {
+            panic!("Expected the seeded topology");
+        };
+        assert_eq!(source, 1);
+        assert_eq!(graph.nodes.len(), 4);
+    }
+
+    #[test]
+    fn test_client_rejects_invalid_neighbors() {
+        let topology = Topology::new().client(1).server(4).link(1, 4);
+        assert!(SimulatedNetwork::start(&topology).is_err());
+    }
+
+    #[test]
+    fn test_crash_is_acknowledged() {
+        let network = SimulatedNetwork::start(&line_topology(0.0)).unwrap();
+        let client = network.client(1).unwrap();
+
+        client.sc_commands.send(DroneCommand::Crash).unwrap();
+        let event = client.service_events.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(
+            event,
+            ServiceEvent::CommandAccepted(ControllerCommand::Crash)
+        );
+    }
+
+    #[test]
+    fn test_crash_with_failed_state_dump_is_reported() {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let missing_directory =
+            std::env::temp_dir().join(format!("missing-{}-{nanos}", std::process::id()));
+        assert!(!missing_directory.exists());
+        let state_dump_path = missing_directory.join("state.json");
+        let network = SimulatedNetwork::start_with_config(&line_topology(0.0), |_| ServiceConfig {
+            state_dump_path: Some(state_dump_path.clone()),
+            ..ServiceConfig::default()
+        })
+        .unwrap();
+        let client = network.client(1).unwrap();
+
+        client.sc_commands.send(DroneCommand::Crash).unwrap();
+        let event = client.service_events.recv_timeout(TIMEOUT).unwrap();
+        assert!(matches!(
+            event,
+            ServiceEvent::CommandFailed(ControllerCommand::Crash, _)
+        ));
+    }
+}
@@ -0,0 +1,485 @@
+//! A server answering the requests of clients under test.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use crossbeam_channel::{Receiver, Sender, select};
+use log::{error, warn};
+use messages::{
+    Message, MessageType, RequestType, ResponseType, ServerType, TextRequest, TextResponse,
+};
+use wg_2024::controller::DroneCommand;
+use wg_2024::network::{NodeId, SourceRoutingHeader};
+use wg_2024::packet::{Ack, FloodRequest, Nack, NackType, NodeType, Packet, PacketType};
+
+use super::send_along_route;
+use crate::packet::codec::{CodecKind, FragmentCodec};
+use crate::packet::envelope::{DecodeOptions, EncodeOptions};
+use crate::packet::utils::{get_reversed_route, message_to_packets, packets_to_message};
+
+/// Builds the content of the response to a request. Returning `None` leaves
+/// the request unanswered.
+pub type Responder = Arc<dyn Fn(&Message) -> Option<MessageType> + Send + Sync>;
+
+/// How a `MockServer` answers requests and in which ways it misbehaves.
+#[derive(Clone)]
+pub struct MockServerBehavior {
+    /// Builds responses, `default_response` unless replaced.
+    pub responder: Responder,
+    /// Amount of received fragments, counted from the first one, that are
+    /// not ACKed.
+    pub unacked_fragments: usize,
+    /// Whether every fragment of a response is sent twice.
+    pub duplicate_fragments: bool,
+    /// Whether ACKs and responses carry a session ID that is off by one.
+    pub wrong_session_ids: bool,
+}
+
+impl Default for MockServerBehavior {
+    fn default() -> Self {
+        MockServerBehavior {
+            responder: Arc::new(default_response),
+            unacked_fragments: 0,
+            duplicate_fragments: false,
+            wrong_session_ids: false,
+        }
+    }
+}
+
+/// Answers discovery requests as a text server and text requests with their
+/// own text. Other messages are left unanswered.
+pub fn default_response(request: &Message) -> Option<MessageType> {
+    let response = match &request.content {
+        MessageType::Request(RequestType::DiscoveryRequest(())) => {
+            ResponseType::DiscoveryResponse(ServerType::Text)
+        }
+        MessageType::Request(RequestType::TextRequest(TextRequest::Text(text))) => {
+            ResponseType::TextResponse(TextResponse::Text(text.clone()))
+        }
+        _ => return None,
+    };
+    Some(MessageType::Response(response))
+}
+
+impl fmt::Debug for MockServerBehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockServerBehavior")
+            .field("unacked_fragments", &self.unacked_fragments)
+            .field("duplicate_fragments", &self.duplicate_fragments)
+            .field("wrong_session_ids", &self.wrong_session_ids)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An in-process server.
+///
+/// The server answers flood requests, ACKs the fragments it receives and
+/// answers every reassembled request along the reversed route of the
+/// request. Received requests are also passed to the channel given on
+/// creation. Fragments of responses NACKed as `Dropped` are re-sent. Like
+/// drones the server is stopped with `DroneCommand::Crash`.
+pub struct MockServer {
+    id: NodeId,
+    packet_recv: Receiver<Packet>,
+    command_recv: Receiver<DroneCommand>,
+    neighbors: HashMap<NodeId, Sender<Packet>>,
+    received_messages: Sender<Message>,
+    behavior: MockServerBehavior,
+    codec: Box<dyn FragmentCodec>,
+    sessions: HashMap<(u64, NodeId), HashMap<u64, Packet>>,
+    completed_sessions: HashSet<(u64, NodeId)>,
+    sent_fragments: HashMap<(u64, u64), Packet>,
+    received_fragments: usize,
+    next_session_id: u64,
+    crashed: bool,
+}
+
+impl MockServer {
+    pub fn new(
+        id: NodeId,
+        packet_recv: Receiver<Packet>,
+        command_recv: Receiver<DroneCommand>,
+        neighbors: HashMap<NodeId, Sender<Packet>>,
+        received_messages: Sender<Message>,
+    ) -> Self {
+        MockServer {
+            id,
+            packet_recv,
+            command_recv,
+            neighbors,
+            received_messages,
+            behavior: MockServerBehavior::default(),
+            codec: CodecKind::default().build(),
+            sessions: HashMap::new(),
+            completed_sessions: HashSet::new(),
+            sent_fragments: HashMap::new(),
+            received_fragments: 0,
+            next_session_id: 0,
+            crashed: false,
+        }
+    }
+
+    #[must_use]
+    pub fn with_behavior(mut self, behavior: MockServerBehavior) -> Self {
+        self.behavior = behavior;
+        self
+    }
+
+    /// Handles packets and commands until the server crashes or its channels
+    /// are disconnected.
+    pub fn run(&mut self) {
+        while !self.crashed {
+            select! {
+                recv(self.command_recv) -> command => match command {
+                    Ok(command) => self.handle_command(command),
+                    Err(_) => return,
+                },
+                recv(self.packet_recv) -> packet => match packet {
+                    Ok(packet) => self.handle_packet(packet),
+                    Err(_) => return,
+                },
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: DroneCommand) {
+        match command {
+            DroneCommand::AddSender(node_id, sender) => {
+                self.neighbors.insert(node_id, sender);
+            }
+            DroneCommand::RemoveSender(node_id) => {
+                self.neighbors.remove(&node_id);
+            }
+            DroneCommand::SetPacketDropRate(_) => {}
+            DroneCommand::Crash => self.crashed = true,
+        }
+    }
+
+    fn handle_packet(&mut self, packet: Packet) {
+        match &packet.pack_type {
+            PacketType::FloodRequest(flood_request) => {
+                let flood_request = flood_request.clone();
+                self.handle_flood_request(packet.session_id, flood_request);
+            }
+            PacketType::MsgFragment(_) => self.handle_fragment(packet),
+            PacketType::Ack(ack) => {
+                self.sent_fragments
+                    .remove(&(packet.session_id, ack.fragment_index));
+            }
+            PacketType::Nack(nack) => {
+                let nack = nack.clone();
+                self.handle_nack(packet.session_id, &nack);
+            }
+            PacketType::FloodResponse(_) => {}
+        }
+    }
+
+    fn handle_flood_request(&self, session_id: u64, mut flood_request: FloodRequest) {
+        flood_request.path_trace.push((self.id, NodeType::Server));
+        let mut response = flood_request.generate_response(session_id);
+        response.routing_header.hop_index = 1;
+        send_along_route(self.id, &self.neighbors, response);
+    }
+
+    fn handle_fragment(&mut self, packet: Packet) {
+        let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+            return;
+        };
+        let Some(sender_id) = packet.routing_header.source() else {
+            warn!("Server {} received a fragment without a sender", self.id);
+            return;
+        };
+        self.received_fragments += 1;
+        if self.received_fragments > self.behavior.unacked_fragments {
+            let ack = Packet {
+                routing_header: get_reversed_route(&packet.routing_header),
+                session_id: self.outgoing_session_id(packet.session_id),
+                pack_type: PacketType::Ack(Ack {
+                    fragment_index: fragment.fragment_index,
+                }),
+            };
+            send_along_route(self.id, &self.neighbors, ack);
+        }
+
+        let session = (packet.session_id, sender_id);
+        if self.completed_sessions.contains(&session) {
+            return;
+        }
+        let total_n_fragments = fragment.total_n_fragments;
+        let fragments = self.sessions.entry(session).or_default();
+        fragments.insert(fragment.fragment_index, packet.clone());
+        if u64::try_from(fragments.len()).ok() != Some(total_n_fragments) {
+            return;
+        }
+
+        let packets: Vec<Packet> = fragments.values().cloned().collect();
+        self.sessions.remove(&session);
+        self.completed_sessions.insert(session);
+        match packets_to_message(&packets, self.codec.as_ref(), &DecodeOptions::default()) {
+            Ok(request) => {
+                self.respond(&request, &packet.routing_header);
+                if self.received_messages.send(request).is_err() {
+                    warn!("Server {} has nobody to pass received messages to", self.id);
+                }
+            }
+            Err(e) => error!("Server {} failed to reassemble a message: {e}", self.id),
+        }
+    }
+
+    /// Sends the response to `request` back along the route it arrived on.
+    fn respond(&mut self, request: &Message, routing_header: &SourceRoutingHeader) {
+        let Some(content) = (self.behavior.responder)(request) else {
+            return;
+        };
+        let session_id = self.next_session_id;
+        self.next_session_id += 1;
+        let response = Message {
+            source: self.id,
+            destination: request.source,
+            session_id,
+            content,
+        };
+
+        let routing_header = get_reversed_route(routing_header);
+        let packets = match message_to_packets(
+            &response,
+            &routing_header,
+            self.codec.as_ref(),
+            &EncodeOptions::default(),
+        ) {
+            Ok(packets) => packets,
+            Err(e) => {
+                error!("Server {} failed to fragment a response: {e}", self.id);
+                return;
+            }
+        };
+        for mut packet in packets {
+            packet.session_id = self.outgoing_session_id(packet.session_id);
+            if let PacketType::MsgFragment(fragment) = &packet.pack_type {
+                self.sent_fragments
+                    .insert((packet.session_id, fragment.fragment_index), packet.clone());
+            }
+            if self.behavior.duplicate_fragments {
+                send_along_route(self.id, &self.neighbors, packet.clone());
+            }
+            send_along_route(self.id, &self.neighbors, packet);
+        }
+    }
+
+    /// Re-sends dropped fragments of responses.
+    fn handle_nack(&self, session_id: u64, nack: &Nack) {
+        let Some(packet) = self.sent_fragments.get(&(session_id, nack.fragment_index)) else {
+            return;
+        };
+        match nack.nack_type {
+            NackType::Dropped => send_along_route(self.id, &self.neighbors, packet.clone()),
+            nack_type => warn!(
+                "Server {} failed to deliver fragment {} of session {session_id}: {nack_type:?}",
+                self.id, nack.fragment_index
+            ),
+        }
+    }
+
+    fn outgoing_session_id(&self, session_id: u64) -> u64 {
+        if self.behavior.wrong_session_ids {
+            session_id.wrapping_add(1)
+        } else {
+            session_id
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::panic)]
+    use super::*;
+    use crossbeam_channel::unbounded;
+
+    const SERVER: NodeId = 3;
+
+    struct Fixture {
+        server: MockServer,
+        drone: Receiver<Packet>,
+        messages: Receiver<Message>,
+    }
+
+    /// Server 3 connected to drone 2, which connects it to client 1.
+    fn fixture(behavior: MockServerBehavior) -> Fixture {
+        let (drone_send, drone) = unbounded();
+        let (messages_send, messages) = unbounded();
+        let (_, packet_recv) = unbounded();
+        let (_, command_recv) = unbounded();
+        let server = MockServer::new(
+            SERVER,
+            packet_recv,
+            command_recv,
+            HashMap::from([(2, drone_send)]),
+            messages_send,
+        )
+        .with_behavior(behavior);
+        Fixture {
+            server,
+            drone,
+            messages,
+        }
+    }
+
+    fn request() -> Message {
+        Message {
+            source: 1,
+            destination: SERVER,
+            session_id: 4,
+            content: MessageType::Request(RequestType::TextRequest(TextRequest::Text(
+                "hello".repeat(60),
+            ))),
+        }
+    }
+
+    /// Fragments of `request()` as they arrive at the server.
+    fn request_packets() -> Vec<Packet> {
+        let routing_header = SourceRoutingHeader::new(vec![1, 2, SERVER], 2);
+        let codec = CodecKind::default().build();
+        message_to_packets(
+            &request(),
+            &routing_header,
+            codec.as_ref(),
+            &EncodeOptions::default(),
+        )
+        .unwrap()
+    }
+
+    fn split_acks(packets: Vec<Packet>) -> (Vec<Packet>, Vec<Packet>) {
+        packets
+            .into_iter()
+            .partition(|packet| matches!(packet.pack_type, PacketType::Ack(_)))
+    }
+
+    #[test]
+    fn test_request_is_acked_and_answered() {
+        let mut fixture = fixture(MockServerBehavior::default());
+        let packets = request_packets();
+        assert!(packets.len() > 1);
+
+        for packet in packets.iter().rev() {
+            fixture.server.handle_packet(packet.clone());
+        }
+        // A duplicate is ACKed again but not answered again.
+        fixture.server.handle_packet(packets[0].clone());
+
+        assert_eq!(fixture.messages.try_recv().unwrap(), request());
+        assert!(fixture.messages.try_recv().is_err());
+
+        let (acks, response) = split_acks(fixture.drone.try_iter().collect());
+        assert_eq!(acks.len(), packets.len() + 1);
+        for packet in acks.iter().chain(&response) {
+            assert_eq!(packet.routing_header.hops, vec![SERVER, 2, 1]);
+            assert_eq!(packet.routing_header.hop_index, 1);
+        }
+        let codec = CodecKind::default().build();
+        let response =
+            packets_to_message(&response, codec.as_ref(), &DecodeOptions::default()).unwrap();
+        assert_eq!(response.source, SERVER);
+        assert_eq!(response.destination, 1);
+        assert_eq!(
+            response.content,
+            MessageType::Response(ResponseType::TextResponse(TextResponse::Text(
+                "hello".repeat(60)
+            )))
+        );
+    }
+
+    #[test]
+    fn test_responder_can_leave_requests_unanswered() {
+        let mut fixture = fixture(MockServerBehavior {
+            responder: Arc::new(|_| None),
+            ..Default::default()
+        });
+        for packet in request_packets() {
+            fixture.server.handle_packet(packet);
+        }
+
+        let (acks, response) = split_acks(fixture.drone.try_iter().collect());
+        assert_eq!(acks.len(), request_packets().len());
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn test_misbehavior() {
+        let mut fixture = fixture(MockServerBehavior {
+            unacked_fragments: 1,
+            duplicate_fragments: true,
+            wrong_session_ids: true,
+            ..Default::default()
+        });
+        let packets = request_packets();
+        for packet in &packets {
+            fixture.server.handle_packet(packet.clone());
+        }
+
+        let (acks, response) = split_acks(fixture.drone.try_iter().collect());
+        assert_eq!(acks.len(), packets.len() - 1);
+        assert!(
+            acks.iter()
+                .all(|ack| ack.session_id == request().session_id + 1)
+        );
+        assert!(!response.is_empty());
+        assert!(response.iter().all(|packet| packet.session_id == 1));
+        for pair in response.chunks(2) {
+            assert_eq!(pair[0].pack_type, pair[1].pack_type);
+        }
+    }
+
+    #[test]
+    fn test_dropped_response_fragment_is_resent() {
+        let mut fixture = fixture(MockServerBehavior::default());
+        for packet in request_packets() {
+            fixture.server.handle_packet(packet);
+        }
+        let (_, response) = split_acks(fixture.drone.try_iter().collect());
+
+        let nack = Packet {
+            routing_header: SourceRoutingHeader::new(vec![2, SERVER], 1),
+            session_id: response[0].session_id,
+            pack_type: PacketType::Nack(Nack {
+                fragment_index: 0,
+                nack_type: NackType::Dropped,
+            }),
+        };
+        fixture.server.handle_packet(nack.clone());
+        let resent = fixture.drone.try_recv().unwrap();
+        assert_eq!(resent.session_id, response[0].session_id);
+        assert_eq!(resent.pack_type, response[0].pack_type);
+
+        // Once ACKed the fragment is no longer re-sent.
+        fixture.server.handle_packet(Packet {
+            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+            ..nack.clone()
+        });
+        fixture.server.handle_packet(nack);
+        assert!(fixture.drone.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_flood_request_is_answered() {
+        let fixture = fixture(MockServerBehavior::default());
+        fixture.server.handle_flood_request(
+            9,
+            FloodRequest {
+                flood_id: 1,
+                initiator_id: 1,
+                path_trace: vec![(1, NodeType::Client), (2, NodeType::Drone)],
+            },
+        );
+        let response = fixture.drone.try_recv().unwrap();
+        let PacketType::FloodResponse(flood_response) = response.pack_type else {
+            panic!("Expected flood response");
+        };
+        assert_eq!(
+            flood_response.path_trace.last(),
+            Some(&(SERVER, NodeType::Server))
+        );
+        assert_eq!(response.routing_header.hops, vec![SERVER, 2, 1]);
+        assert_eq!(response.routing_header.hop_index, 1);
+    }
+}